@@ -5,41 +5,48 @@ const s2 r3
 const a0 r4
 const rt r5
 
+#> test fib_10
+#> set a0 10
+#> call fib
+#> expect rt 55
+#> expect stack 0
+
+#> test fib_base
+#> set a0 1
+#> call fib
+#> expect rt 1
+
 main:
-  li a0 10
+  li   a0 10
   call fib
-  mv io rt
+  mv   io rt
+
+halt:
+  jmp halt
 
+.func fib in a0 out rt clobbers a0,s0,s1,s2
 fib:
   mv s0 a0
 
-  cmpi s0 2
+  cmpi  s0 2
   li.le rt 1
   ret.le
 
   # fib(n - 1)
-  push s0
-  push s1
-  push s2
-  sub s0 s0 1
-  mv a0 s0
-  call fib
-  pop s2
-  pop s1
-  pop s0
-  mv s1 rt
+  pushm s0,s1,s2
+  sub   s0 s0 1
+  mv    a0 s0
+  call  fib
+  popm  s0,s1,s2
+  mv    s1 rt
 
   # fib(n - 2)
-  push s0
-  push s1
-  push s2
-  sub s0 s0 2
-  mv a0 s0
-  call fib
-  pop s2
-  pop s1
-  pop s0
-  mv s2 rt
+  pushm s0,s1,s2
+  sub   s0 s0 2
+  mv    a0 s0
+  call  fib
+  popm  s0,s1,s2
+  mv    s2 rt
 
   add rt s1 s2
 
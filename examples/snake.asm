@@ -1,26 +1,26 @@
-const head_x r1
-const head_y r2
-const food_x r3
-const food_y r4
-const x r5
-const y r6
-const nx r7
-const ny r8
+const head_x   r1
+const head_y   r2
+const food_x   r3
+const food_y   r4
+const x        r5
+const y        r6
+const nx       r7
+const ny       r8
 const key_code r9
-const t0 r10
-const t1 r11
-const i r12
+const t0       r10
+const t1       r11
+const i        r12
 
 const rt r13
 
-const que_len r14
+const que_len  r14
 const que_head r15
-const que_val r16
-const que_tmp r17
-const que_i r18
-const que_cur r19
+const que_val  r16
+const que_tmp  r17
+const que_i    r18
+const que_cur  r19
 
-const SCREEN_WIDTH 64
+const SCREEN_WIDTH  64
 const SCREEN_HEIGHT 36
 
 const MAX_X 63 # SCREEN_WIDTH - 1
@@ -31,9 +31,9 @@ const COLOR_BODY 0xFFFFFF
 const COLOR_HEAD 0xFF0000
 const COLOR_FOOD 0xFFFF00
 
-const KEY_UP 70
-const KEY_DOWN 72
-const KEY_LEFT 69
+const KEY_UP    70
+const KEY_DOWN  72
+const KEY_LEFT  69
 const KEY_RIGHT 71
 
 const INIT_X 5
@@ -46,35 +46,35 @@ jmp main
 
 
 init_screen:
-	clr x
-	clr y
-	col COLOR_BACK
-	draw_back:
-		spx x y
-		inc x
-		blt x SCREEN_WIDTH draw_back
-		inc y
-		clr x
-		blt y SCREEN_HEIGHT draw_back
+  clr x
+  clr y
+  col COLOR_BACK
+draw_back:
+  spx x y
+  inc x
+  blt x SCREEN_WIDTH draw_back
+  inc y
+  clr x
+  blt y SCREEN_HEIGHT draw_back
 
   ret
 
 
 init_snake:
   col COLOR_HEAD
-  li head_x INIT_X
-  li head_y INIT_Y
+  li  head_x INIT_X
+  li  head_y INIT_Y
   spx head_x head_y
 
   col COLOR_BODY
-  li i 3
-  init_body_loop:
-    sub x head_x i
-    mv y head_y
-    call body_push
-    spx x y
-    dec i
-    bge i 1 init_body_loop
+  li  i 3
+init_body_loop:
+  sub  x head_x i
+  mv   y head_y
+  call body_push
+  spx  x y
+  dec  i
+  bge  i 1 init_body_loop
 
   ret
 
@@ -87,81 +87,81 @@ move_snake:
 
   ret
 
-  move_snake_up:
-    mv nx head_x
-    sub ny head_y 1
-    jmp move_snake_common
-
-  move_snake_down:
-    mv nx head_x
-    add ny head_y 1
-    jmp move_snake_common
-
-  move_snake_left:
-    sub nx head_x 1
-    mv ny head_y
-    jmp move_snake_common
-
-  move_snake_right:
-    add nx head_x 1
-    mv ny head_y
-    jmp move_snake_common
-
-  move_snake_common:
-    # 旧头变成身体
-    mv x head_x
-    mv y head_y
-    call body_push
-    col COLOR_BODY
-    spx x y
-
-    # 允许环绕
-    cmp nx 0
-    li.lt nx MAX_X
-    cmp nx SCREEN_WIDTH
-    li.ge nx 0
-    cmp ny 0
-    li.lt ny MAX_Y
-    cmp ny SCREEN_HEIGHT
-    li.ge ny 0
-
-    # 画新头
-    mv head_x nx
-    mv head_y ny
-    col COLOR_HEAD
-    spx head_x head_y
-
-    # 检查是否撞到自己
-    mv x head_x
-    mv y head_y
-    call body_contains
-    beq rt 1 lose_loop
-
-    # 吃到食物则不动尾巴，重新生成食物
-    bne head_x food_x move_snake_not_eat
-    bne head_y food_y move_snake_not_eat
-    call gen_food
-    ret
-
-    # 未吃到则弹出尾巴
-    move_snake_not_eat:
-      call body_pop
-      col COLOR_BACK
-      spx x y
-      ret
+move_snake_up:
+  mv  nx head_x
+  sub ny head_y 1
+  jmp move_snake_common
+
+move_snake_down:
+  mv  nx head_x
+  add ny head_y 1
+  jmp move_snake_common
+
+move_snake_left:
+  sub nx head_x 1
+  mv  ny head_y
+  jmp move_snake_common
+
+move_snake_right:
+  add nx head_x 1
+  mv  ny head_y
+  jmp move_snake_common
+
+move_snake_common:
+  # 旧头变成身体
+  mv   x head_x
+  mv   y head_y
+  call body_push
+  col  COLOR_BODY
+  spx  x y
+
+  # 允许环绕
+  cmp   nx 0
+  li.lt nx MAX_X
+  cmp   nx SCREEN_WIDTH
+  li.ge nx 0
+  cmp   ny 0
+  li.lt ny MAX_Y
+  cmp   ny SCREEN_HEIGHT
+  li.ge ny 0
+
+  # 画新头
+  mv  head_x nx
+  mv  head_y ny
+  col COLOR_HEAD
+  spx head_x head_y
+
+  # 检查是否撞到自己
+  mv   x head_x
+  mv   y head_y
+  call body_contains
+  beq  rt 1 lose_loop
+
+  # 吃到食物则不动尾巴，重新生成食物
+  bne  head_x food_x move_snake_not_eat
+  bne  head_y food_y move_snake_not_eat
+  call gen_food
+  ret
+
+# 未吃到则弹出尾巴
+move_snake_not_eat:
+  call body_pop
+  col  COLOR_BACK
+  spx  x y
+  ret
 
 
 gen_food:
-  mv x rng
-  mv y rng
+  mv  x rng
+  mv  y rng
   mod x x SCREEN_WIDTH
   mod y y SCREEN_HEIGHT
 
   call body_contains
-  beq rt 1 gen_food
+  beq  rt 1 gen_food
 
-  mv food_x x
-  mv food_y y
+  mv  food_x x
+  mv  food_y y
   col COLOR_FOOD
   spx food_x food_y
 
@@ -169,20 +169,20 @@ gen_food:
 
 
 body_push:
-  shl que_val x 8
-  or que_val que_val y
+  shl  que_val x 8
+  or   que_val que_val y
   call queue_push
   ret
 
 body_pop:
   call queue_pop
-  shr x que_val 8
-  and y que_val 0xFF
+  shr  x que_val 8
+  and  y que_val 0xFF
   ret
 
 body_contains:
-  shl que_val x 8
-  or que_val que_val y
+  shl  que_val x 8
+  or   que_val que_val y
   call queue_contains
   ret
 
@@ -205,9 +205,9 @@ queue_push:
   li rt 0
   ret
 
-  queue_push_full:
-    li rt 1
-    ret
+queue_push_full:
+  li rt 1
+  ret
 
 # if (len == 0) return 1
 # t = (head - len + SIZE) % SIZE
@@ -228,9 +228,9 @@ queue_pop:
   li rt 0
   ret
 
-  queue_pop_empty:
-    li rt 1
-    ret
+queue_pop_empty:
+  li rt 1
+  ret
 
 # if (len == 0) return 0
 # for i in [0, len):
@@ -247,25 +247,25 @@ queue_contains:
 
   clr que_i
 
-  queue_contains_loop:
-    beq que_i que_len queue_contains_not_found
+queue_contains_loop:
+  beq que_i que_len queue_contains_not_found
 
-    lw que_cur que_tmp 0
-    beq que_cur que_val queue_contains_found
+  lw  que_cur que_tmp 0
+  beq que_cur que_val queue_contains_found
 
-    inc que_tmp
-    mod que_tmp que_tmp QUEUE_SIZE
+  inc que_tmp
+  mod que_tmp que_tmp QUEUE_SIZE
 
-    inc que_i
-    jmp queue_contains_loop
+  inc que_i
+  jmp queue_contains_loop
 
-  queue_contains_found:
-    li rt 1
-    ret
+queue_contains_found:
+  li rt 1
+  ret
 
-  queue_contains_not_found:
-    li rt 0
-    ret
+queue_contains_not_found:
+  li rt 0
+  ret
 
 
 read_key:
@@ -278,15 +278,15 @@ read_key:
 
   ret
 
-  read_key_ok:
-    sub t1 key_code t0
-    beq t1 2 read_key_ret
-    beq t1 0xFFFFFFFE read_key_ret # -2
+read_key_ok:
+  sub t1 key_code t0
+  beq t1 2 read_key_ret
+  beq t1 0xFFFFFFFE read_key_ret # -2
 
-    mv key_code t0
+  mv key_code t0
 
-  read_key_ret:
-    ret
+read_key_ret:
+  ret
 
 
 main:
@@ -294,15 +294,15 @@ main:
   call init_snake
   call gen_food
 
-  main_loop:
-    li i 100
-    sleep:
-      dec i
-      bgt i 0 sleep
+main_loop:
+  li i 100
+sleep:
+  dec i
+  bgt i 0 sleep
 
-    call read_key
-    call move_snake
-    jmp main_loop
+  call read_key
+  call move_snake
+  jmp  main_loop
 
 
 lose_loop:
@@ -1,10 +1,10 @@
 # leetcode: trapping-rain-water
 # solutions/5126477
 
-const l r1
-const r r2
-const lmax r3
-const rmax r4
+const l     r1
+const r     r2
+const lmax  r3
+const rmax  r4
 const water r5
 
 const t0 r6
@@ -14,18 +14,41 @@ const a0 r8
 const a1 r9
 const rt r10
 
+#> test solve
+#> mem 1 1
+#> mem 3 2
+#> mem 4 1
+#> mem 6 1
+#> mem 7 3
+#> mem 8 2
+#> mem 9 1
+#> mem 10 2
+#> mem 11 1
+#> call solve
+#> expect rt 6
+#> expect stack 0
+
+#> test max
+#> set a0 3
+#> set a1 5
+#> call max
+#> expect rt 5
+
 main:
   li t0 16
-  input:
-    sw t1 io 0
-    inc t1
-    dec t0
-    bgt t0 0 input
+input:
+  sw  t1 io 0
+  inc t1
+  dec t0
+  bgt t0 0 input
 
   call solve
 
   mv io rt
 
+halt:
+  jmp halt
+
 solve:
   # l = 0
   li l 0
@@ -36,44 +59,37 @@ solve:
   # rmax = ht[r]
   lw rmax r 0
 
-  # while l < r:
-  while: bge l r endwhile
-    # if lmax < rmax:
-    bge lmax rmax else
+  .while l lt r
+    .if lmax lt rmax
       # l++
       inc l
       # lmax = max(lmax, ht[l])
-      lw a0 l 0
-      mv a1 lmax
+      lw   a0 l 0
+      mv   a1 lmax
       call max
-      mv lmax rt
+      mv   lmax rt
       # water += lmax - ht[l]
       sub t1 lmax a0
       add water water t1
-
-      jmp endif
-
-    else:
+    .else
       # r--
       dec r
       # rmax = max(rmax, ht[r])
-      lw a0 r 0
-      mv a1 rmax
+      lw   a0 r 0
+      mv   a1 rmax
       call max
-      mv rmax rt
+      mv   rmax rt
       # water += rmax - ht[r]
       sub t1 rmax a0
       add water water t1
+    .endif
+  .endwhile
 
-    endif:
-      jmp while
-
-  endwhile:
-    mv rt water
-    ret
+  mv rt water
+  ret
 
 max:
-  cmp a0 a1
+  cmp   a0 a1
   mv.gt rt a0
   mv.le rt a1
   ret
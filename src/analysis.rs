@@ -0,0 +1,78 @@
+mod calling;
+mod cfg;
+mod flags;
+mod registers;
+mod stack;
+mod tmp_clobber;
+mod unused;
+
+use std::fmt::Display;
+
+use crate::assembler::Program;
+
+pub use cfg::{Cfg, Flow};
+pub use stack::StackAnalysis;
+
+/// The general purpose registers and `tmp`, the others are devices or read-only.
+const TRACKED: u32 = 0x01FF_FFFE | 1 << 31;
+
+fn tracked(reg: u32) -> bool {
+    TRACKED & (1 << reg) != 0
+}
+
+/// A problem found in a program that assembled successfully.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Index of the original source line.
+    pub line: usize,
+    pub source: String,
+    pub message: String,
+}
+
+impl Warning {
+    fn at(program: &Program, addr: usize, message: String) -> Self {
+        let (line, source) = program.source_map[addr].clone();
+        Warning {
+            line,
+            source,
+            message,
+        }
+    }
+
+    /// A warning about the definition of a constant or a label.
+    fn at_definition(program: &Program, name: &str, message: String) -> Self {
+        let (line, source) = program.definitions[name].clone();
+        Warning {
+            line,
+            source,
+            message,
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Warning at line {}: '{}' ({})",
+            self.line + 1,
+            self.source,
+            self.message
+        )
+    }
+}
+
+/// Runs all checks over `program`, returns the warnings ordered by line.
+pub fn analyze(program: &Program) -> Vec<Warning> {
+    let cfg = Cfg::new(program);
+    let mut warnings = tmp_clobber::check(program, &cfg);
+    warnings.extend(unused::check(program, &cfg));
+    warnings.extend(registers::check(program, &cfg));
+    warnings.extend(flags::check(program, &cfg));
+    warnings.extend(calling::check(program, &cfg));
+    warnings.extend(StackAnalysis::new(program, &cfg).warnings());
+
+    warnings.sort_by_key(|w| w.line);
+    warnings.dedup();
+    warnings
+}
@@ -0,0 +1,329 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::{
+        Warning,
+        cfg::{Cfg, Flow, Function},
+        flags::sets_flags,
+        tracked,
+    },
+    assembler::Program,
+    instructions::{OPCODES, fmt_reg, reg_usage},
+    signature::Signature,
+};
+
+/// What a register or a stack slot may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    /// The value the register had on entry of the function.
+    Entry(u32),
+    /// Overwritten by the call at this address, whose callee declares the register clobbered.
+    Clobbered(usize),
+    Other,
+}
+
+type Values = BTreeSet<Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    regs: Vec<Values>,
+    /// The values pushed since the entry, `None` once the depth differs between paths.
+    stack: Option<Vec<Values>>,
+    /// The state on the paths where the condition of the last predicated word held, until
+    /// the flags change, so that `li.le rt 1` followed by `ret.le` returns with `rt` written.
+    taken: Option<(u32, Box<State>)>,
+}
+
+impl State {
+    fn entry() -> Self {
+        State {
+            regs: (0..32)
+                .map(|reg| Values::from([Value::Entry(reg)]))
+                .collect(),
+            stack: Some(Vec::new()),
+            taken: None,
+        }
+    }
+
+    /// The state seen by a word predicated with `cond`.
+    fn when(&self, cond: u32) -> &State {
+        match &self.taken {
+            Some((c, taken)) if cond != 0 && *c == cond => taken,
+            _ => self,
+        }
+    }
+
+    fn union(&self, other: &State) -> State {
+        let union = |a: &Values, b: &Values| a.union(b).copied().collect();
+        State {
+            regs: self
+                .regs
+                .iter()
+                .zip(&other.regs)
+                .map(|(a, b)| union(a, b))
+                .collect(),
+            stack: match (&self.stack, &other.stack) {
+                (Some(a), Some(b)) if a.len() == b.len() => {
+                    Some(a.iter().zip(b).map(|(a, b)| union(a, b)).collect())
+                }
+                _ => None,
+            },
+            taken: match (&self.taken, &other.taken) {
+                (Some((a, x)), Some((b, y))) if a == b => Some((*a, Box::new(x.union(y)))),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Checks the `.func` signatures of labels.
+///
+/// Annotated functions must only read their inputs, write their outputs on every path
+/// and preserve the registers that are neither outputs nor clobbered, `push`/`pop` pairs
+/// included. Every caller of an annotated function must not read a clobbered register
+/// before writing it again.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Warning> {
+    let may_write = cfg.summarize(
+        |addr| {
+            let (_, writes) = reg_usage(program.codes[addr]).unwrap_or_default();
+            writes.into_iter().fold(0u32, |mask, reg| mask | 1 << reg)
+        },
+        |a, b| *a |= b,
+    );
+    let signature = |target: usize| {
+        program
+            .labels
+            .get_by_right(&target)
+            .and_then(|name| program.signatures.get(name))
+    };
+
+    let mut warnings = Vec::new();
+    for func in &cfg.functions {
+        let own = signature(func.entry);
+        let states = dataflow(program, cfg, func, |addr, target, state| {
+            for reg in 0..32 {
+                let value = match signature(target) {
+                    Some(sig) if sig.clobbers.contains(&reg) => Value::Clobbered(addr),
+                    Some(sig) if sig.may_write(reg) => Value::Other,
+                    None if may_write.get(&target).is_some_and(|m| m & 1 << reg != 0) => {
+                        Value::Other
+                    }
+                    _ => continue,
+                };
+                state.regs[reg as usize] = Values::from([value]);
+            }
+        });
+
+        for (addr, state) in states.iter().enumerate() {
+            let Some(state) = state else {
+                continue;
+            };
+            let state = state.when(condition(program, addr));
+            let mut warn = |message| warnings.push(Warning::at(program, addr, message));
+
+            let reads = match cfg.flow(addr) {
+                Flow::Call(target) => signature(target).map_or(Vec::new(), |sig| sig.ins.clone()),
+                _ if is(program, addr, "push") => Vec::new(),
+                _ => reg_usage(program.codes[addr]).unwrap_or_default().0,
+            };
+            for reg in reads.into_iter().filter(|&reg| tracked(reg)) {
+                for &value in &state.regs[reg as usize] {
+                    match value {
+                        Value::Clobbered(call) => warn(format!(
+                            "'{}' may have been clobbered by the call at {}",
+                            fmt_reg(reg),
+                            program.location(call)
+                        )),
+                        Value::Entry(entry)
+                            if own.is_some_and(|sig| !sig.ins.contains(&entry)) && entry != 31 =>
+                        {
+                            warn(format!(
+                                "'{}' reads '{}', which is not among its inputs",
+                                func.name,
+                                fmt_reg(entry)
+                            ))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if let (Some(sig), Flow::Ret) = (own, cfg.flow(addr)) {
+                check_return(sig, state, |message| {
+                    warn(format!("'{}' {}", func.name, message))
+                });
+            }
+        }
+    }
+
+    warnings
+}
+
+fn check_return(sig: &Signature, state: &State, mut warn: impl FnMut(String)) {
+    for reg in (0..32).filter(|&reg| tracked(reg)) {
+        let values = &state.regs[reg as usize];
+        if sig.outs.contains(&reg) {
+            if values.contains(&Value::Entry(reg)) {
+                warn(format!("may return without writing '{}'", fmt_reg(reg)));
+            }
+        } else if !sig.may_write(reg) && *values != Values::from([Value::Entry(reg)]) {
+            warn(format!("may not preserve '{}'", fmt_reg(reg)));
+        }
+    }
+}
+
+fn condition(program: &Program, addr: usize) -> u32 {
+    let code = program.codes[addr];
+    OPCODES
+        .get(&(code >> 25))
+        .map_or(0, |instr| instr.decode(code).0)
+}
+
+fn is(program: &Program, addr: usize, name: &str) -> bool {
+    OPCODES
+        .get(&(program.codes[addr] >> 25))
+        .is_some_and(|instr| instr.name() == name)
+}
+
+/// The values of the registers and the stack on entry of each address of `func`,
+/// `call` updates the state after a call with `(addr, target, state)`.
+fn dataflow(
+    program: &Program,
+    cfg: &Cfg,
+    func: &Function,
+    call: impl Fn(usize, usize, &mut State),
+) -> Vec<Option<State>> {
+    let len = program.codes.len();
+    let mut states: Vec<Option<State>> = vec![None; len];
+    states[func.entry] = Some(State::entry());
+    let mut worklist = vec![func.entry];
+
+    while let Some(addr) = worklist.pop() {
+        let Some(state) = states[addr].clone() else {
+            continue;
+        };
+
+        let step = |state: &State| {
+            let mut out = state.clone();
+            out.taken = None;
+
+            let (reads, writes) = reg_usage(program.codes[addr]).unwrap_or_default();
+            let pushed = if is(program, addr, "push") {
+                Some(state.regs[reads[0] as usize].clone())
+            } else if is(program, addr, "pushi") {
+                Some(Values::from([Value::Other]))
+            } else {
+                None
+            };
+            if let (Some(stack), Some(value)) = (&mut out.stack, pushed) {
+                stack.push(value);
+            }
+
+            let popped = match &mut out.stack {
+                Some(stack) if is(program, addr, "pop") => stack.pop(),
+                _ => None,
+            };
+            let popped = popped.unwrap_or(Values::from([Value::Other]));
+            for reg in writes.into_iter().filter(|&reg| tracked(reg)) {
+                out.regs[reg as usize] = popped.clone();
+            }
+
+            if let Flow::Call(target) = cfg.flow(addr)
+                && target < len
+            {
+                call(addr, target, &mut out);
+            }
+            out
+        };
+
+        let cond = condition(program, addr);
+        let mut out = step(&state);
+        if cond != 0 {
+            let taken = step(state.when(cond));
+            out = out.union(&state);
+            out.taken = Some((cond, Box::new(taken)));
+        } else if let Some((c, taken)) = &state.taken {
+            out.taken = Some((*c, Box::new(step(taken))));
+        }
+        if sets_flags(program.codes[addr]) || matches!(cfg.flow(addr), Flow::Call(_)) {
+            out.taken = None;
+        }
+
+        for succ in cfg.successors(addr) {
+            let merged = match &states[succ] {
+                Some(old) => {
+                    let merged = old.union(&out);
+                    if merged == *old {
+                        continue;
+                    }
+                    merged
+                }
+                None => out.clone(),
+            };
+            states[succ] = Some(merged);
+            worklist.push(succ);
+        }
+    }
+
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn calling() {
+        let program = assemble(
+            "
+            const a0 r1
+            const rt r13
+
+            main:
+                li a0 5
+                li r10 1
+                call double
+                mv io rt
+                mv io r10
+                call leaky
+            halt:
+                jmp halt
+
+            .func double in a0 out rt clobbers r10
+            double:
+                add r10 a0 a0
+                mv rt r10
+                ret
+
+            .func leaky in r1 out r13
+            leaky:
+                push r2
+                push r3
+                add r2 r1 r4
+                cmp r1 0
+                li.eq r13 1
+                pop r2
+                pop r3
+                ret
+
+            .func sign in r1 out r13
+            sign:
+                cmp r1 0
+                li.eq r13 0
+                ret.eq
+                li r13 1
+                ret
+            ",
+        );
+        let warnings = check(&program, &Cfg::new(&program));
+
+        assert_snapshot!(fmt_warnings(&warnings), @r"
+        Warning at line 10: 'mv io r10' ('r10' may have been clobbered by the call at line 8: 'call double')
+        Warning at line 25: 'add r2 r1 r4' ('leaky' reads 'r4', which is not among its inputs)
+        Warning at line 30: 'ret' ('leaky' may not preserve 'r2')
+        Warning at line 30: 'ret' ('leaky' may not preserve 'r3')
+        Warning at line 30: 'ret' ('leaky' may return without writing 'r13')
+        ");
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{assembler::Program, instructions::OPCODES};
+
+/// How control leaves a word.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump(usize),
+    /// A `b*` branch or a predicated `jmp`, which may also fall through.
+    Branch(usize),
+    /// Continues at the next word once the callee returns.
+    Call(usize),
+    Ret,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Next,
+    Jump,
+    Taken,
+}
+
+/// A straight run of words, entered only at its start and left only at its end.
+#[derive(Debug)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    /// Successor blocks within the same function.
+    pub succs: Vec<(usize, EdgeKind)>,
+    /// The target of the `call` ending the block.
+    pub call: Option<usize>,
+    /// Whether the block may `ret`.
+    pub returns: bool,
+}
+
+/// The blocks reachable from an entry without following calls.
+#[derive(Debug)]
+pub struct Function {
+    pub entry: usize,
+    pub name: String,
+    pub blocks: Vec<usize>,
+}
+
+/// The control-flow graph of a program.
+///
+/// Functions are the targets of `call`s and the labels with a `.func` signature, plus
+/// the entry at address 0. Predicated words other than jumps, calls and returns stay
+/// inside their block.
+pub struct Cfg<'a> {
+    program: &'a Program,
+    flows: Vec<(Flow, bool)>,
+    block_at: Vec<usize>,
+    pub blocks: Vec<Block>,
+    pub functions: Vec<Function>,
+}
+
+impl<'a> Cfg<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let len = program.codes.len();
+        let flows = program
+            .codes
+            .iter()
+            .map(|&code| flow(code))
+            .collect::<Vec<_>>();
+
+        let mut leaders = BTreeSet::from([0]);
+        leaders.extend(program.labels.right_values().copied());
+        for (addr, (flow, _)) in flows.iter().enumerate() {
+            match *flow {
+                Flow::Next => continue,
+                Flow::Jump(target) | Flow::Branch(target) | Flow::Call(target) => {
+                    leaders.insert(target);
+                }
+                Flow::Ret => {}
+            }
+            leaders.insert(addr + 1);
+        }
+        let leaders = leaders.into_iter().filter(|&a| a < len).collect::<Vec<_>>();
+
+        let mut block_at = vec![0; len];
+        let mut blocks = Vec::new();
+        for (i, &start) in leaders.iter().enumerate() {
+            let end = leaders.get(i + 1).copied().unwrap_or(len);
+            block_at[start..end].fill(i);
+            blocks.push(Block {
+                start,
+                end,
+                succs: Vec::new(),
+                call: None,
+                returns: false,
+            });
+        }
+
+        let mut cfg = Cfg {
+            program,
+            flows,
+            block_at,
+            blocks,
+            functions: Vec::new(),
+        };
+
+        for i in 0..cfg.blocks.len() {
+            let last = cfg.blocks[i].end - 1;
+            let flow = cfg.flow(last);
+
+            let mut succs = match flow {
+                Flow::Jump(target) => vec![(target, EdgeKind::Jump)],
+                Flow::Branch(target) => vec![(target, EdgeKind::Taken)],
+                _ => Vec::new(),
+            };
+            if cfg.successors(last).contains(&(last + 1)) {
+                succs.push((last + 1, EdgeKind::Next));
+            }
+            let succs = succs
+                .into_iter()
+                .filter(|&(addr, _)| addr < len)
+                .map(|(addr, kind)| (cfg.block_at[addr], kind))
+                .collect();
+
+            let block = &mut cfg.blocks[i];
+            block.succs = succs;
+            block.call = match flow {
+                Flow::Call(target) if target < len => Some(target),
+                _ => None,
+            };
+            block.returns = flow == Flow::Ret;
+        }
+
+        let mut entries = BTreeSet::from([0]);
+        entries.extend(cfg.blocks.iter().filter_map(|b| b.call));
+        entries.extend(
+            program
+                .signatures
+                .keys()
+                .filter_map(|name| program.labels.get_by_left(name)),
+        );
+        cfg.functions = entries
+            .into_iter()
+            .filter(|&entry| entry < len)
+            .map(|entry| cfg.function_at(entry))
+            .collect();
+
+        cfg
+    }
+
+    fn function_at(&self, entry: usize) -> Function {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![self.block_at[entry]];
+
+        while let Some(block) = stack.pop() {
+            if seen.insert(block) {
+                stack.extend(self.blocks[block].succs.iter().map(|&(b, _)| b));
+            }
+        }
+
+        Function {
+            entry,
+            name: self.program.symbol(entry),
+            blocks: seen.into_iter().collect(),
+        }
+    }
+
+    pub fn flow(&self, addr: usize) -> Flow {
+        self.flows[addr].0
+    }
+
+    /// Whether the word at `addr` is predicated.
+    pub fn conditional(&self, addr: usize) -> bool {
+        self.flows[addr].1
+    }
+
+    /// The addresses that may execute after `addr` within the same function.
+    pub fn successors(&self, addr: usize) -> Vec<usize> {
+        let (flow, conditional) = self.flows[addr];
+        let next = addr + 1;
+
+        let mut succs = match flow {
+            Flow::Next | Flow::Call(_) => vec![next],
+            Flow::Jump(target) => vec![target],
+            Flow::Branch(target) => vec![target, next],
+            Flow::Ret => vec![],
+        };
+        if conditional && !succs.contains(&next) {
+            succs.push(next);
+        }
+
+        succs.retain(|&a| a < self.program.codes.len());
+        succs
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Summarizes each function by the entry address, as the fixpoint of
+    /// `summary(f) = own(words of f) ∪ summary(callees of f)`.
+    pub fn summarize<T: Clone + PartialEq>(
+        &self,
+        own: impl Fn(usize) -> T,
+        union: impl Fn(&mut T, &T),
+    ) -> HashMap<usize, T> {
+        let mut summaries = HashMap::new();
+        for func in &self.functions {
+            let mut summary = own(func.entry);
+            for &block in &func.blocks {
+                let block = &self.blocks[block];
+                for addr in block.start..block.end {
+                    union(&mut summary, &own(addr));
+                }
+            }
+            summaries.insert(func.entry, summary);
+        }
+
+        loop {
+            let mut changed = false;
+            for func in &self.functions {
+                let mut summary = summaries[&func.entry].clone();
+                for &block in &func.blocks {
+                    if let Some(callee) = self.blocks[block].call {
+                        union(&mut summary, &summaries[&callee]);
+                    }
+                }
+                if summary != summaries[&func.entry] {
+                    summaries.insert(func.entry, summary);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return summaries;
+            }
+        }
+    }
+
+    /// Exports a function as a Graphviz DOT graph, its blocks labeled by source lines.
+    pub fn to_dot(&self, func: &Function) -> String {
+        let mut dot = format!("digraph {} {{\n", dot_str(&func.name));
+        dot += "    node [shape=box, fontname=\"monospace\"];\n";
+
+        let mut callees = BTreeSet::new();
+        let mut returns = false;
+
+        for &i in &func.blocks {
+            let block = &self.blocks[i];
+
+            let mut label = String::new();
+            if let Some(name) = self.program.labels.get_by_right(&block.start) {
+                label += &format!("{}:\\l", name);
+            }
+            let mut last_line = None;
+            for (line, source) in &self.program.source_map[block.start..block.end] {
+                if last_line != Some(*line) {
+                    label += &format!("{:>4}: {}\\l", line + 1, dot_escape(source));
+                    last_line = Some(*line);
+                }
+            }
+            let style = if block.start == func.entry {
+                ", style=bold"
+            } else {
+                ""
+            };
+            dot += &format!("    b{} [label=\"{}\"{}];\n", block.start, label, style);
+
+            for &(succ, kind) in &block.succs {
+                let attrs = match kind {
+                    EdgeKind::Taken => " [label=\"taken\"]",
+                    EdgeKind::Next | EdgeKind::Jump => "",
+                };
+                dot += &format!(
+                    "    b{} -> b{}{};\n",
+                    block.start, self.blocks[succ].start, attrs
+                );
+            }
+            if let Some(target) = block.call {
+                dot += &format!("    b{} -> call{} [style=dashed];\n", block.start, target);
+                callees.insert(target);
+            }
+            if block.returns {
+                dot += &format!("    b{} -> ret;\n", block.start);
+                returns = true;
+            }
+        }
+
+        for target in callees {
+            dot += &format!(
+                "    call{} [shape=ellipse, label={}];\n",
+                target,
+                dot_str(&format!("call {}", self.program.symbol(target)))
+            );
+        }
+        if returns {
+            dot += "    ret [shape=ellipse];\n";
+        }
+
+        dot += "}\n";
+        dot
+    }
+}
+
+fn flow(code: u32) -> (Flow, bool) {
+    let Some(instr) = OPCODES.get(&(code >> 25)) else {
+        return (Flow::Next, false);
+    };
+    let (cond, ops) = instr.decode(code);
+    let conditional = cond != 0;
+
+    let flow = match instr.name() {
+        "jmp" if conditional => Flow::Branch(ops[0] as usize),
+        "jmp" => Flow::Jump(ops[0] as usize),
+        "beq" | "bne" | "blt" | "ble" | "bgt" | "bge" => Flow::Branch(ops[2] as usize),
+        "call" => Flow::Call(ops[0] as usize),
+        "ret" => Flow::Ret,
+        _ => Flow::Next,
+    };
+
+    (flow, conditional)
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_str(s: &str) -> String {
+    format!("\"{}\"", dot_escape(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    const SRC: &str = "
+        main:
+            li r1 3
+            loop:
+                call work
+                dec r1
+                bne r1 zero loop
+        halt:
+            jmp halt
+        work:
+            cmp r1 2
+            ret.eq
+            li r2 1
+            ret
+    ";
+
+    #[test]
+    fn blocks() {
+        let program = assemble(SRC);
+        let cfg = Cfg::new(&program);
+
+        let blocks = cfg
+            .blocks
+            .iter()
+            .map(|b| {
+                let succs = b
+                    .succs
+                    .iter()
+                    .map(|&(s, kind)| format!("{}({:?})", cfg.blocks[s].start, kind))
+                    .collect::<Vec<_>>();
+                format!(
+                    "{}..{} -> [{}] call={:?} ret={}",
+                    b.start,
+                    b.end,
+                    succs.join(", "),
+                    b.call,
+                    b.returns
+                )
+            })
+            .collect::<Vec<_>>();
+        let functions = cfg
+            .functions
+            .iter()
+            .map(|f| format!("{} @ {}: {:?}", f.name, f.entry, f.blocks))
+            .collect::<Vec<_>>();
+
+        assert_snapshot!(format!("{}\n\n{}", blocks.join("\n"), functions.join("\n")), @r"
+        0..1 -> [1(Next)] call=None ret=false
+        1..2 -> [2(Next)] call=Some(5) ret=false
+        2..4 -> [1(Taken), 4(Next)] call=None ret=false
+        4..5 -> [4(Jump)] call=None ret=false
+        5..7 -> [7(Next)] call=None ret=true
+        7..9 -> [] call=None ret=true
+
+        main @ 0: [0, 1, 2, 3]
+        work @ 5: [4, 5]
+        ");
+    }
+
+    #[test]
+    fn dot() {
+        let program = assemble(SRC);
+        let cfg = Cfg::new(&program);
+
+        assert_snapshot!(cfg.to_dot(cfg.function("work").unwrap()), @r#"
+        digraph "work" {
+            node [shape=box, fontname="monospace"];
+            b5 [label="work:\l  11: cmp r1 2\l  12: ret.eq\l", style=bold];
+            b5 -> b7;
+            b5 -> ret;
+            b7 [label="  13: li r2 1\l  14: ret\l"];
+            b7 -> ret;
+            ret [shape=ellipse];
+        }
+        "#);
+    }
+}
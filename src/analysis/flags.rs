@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::{
+        Warning,
+        cfg::{Cfg, Flow, Function},
+    },
+    assembler::Program,
+    instructions::OPCODES,
+};
+
+/// Where the flags seen by a word may come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    /// Nothing set the flags since the start of the program.
+    Missing,
+    /// The flags were set before the function was called.
+    Caller,
+    /// A `cmp`/`cmpi` at this address.
+    Cmp(usize),
+    /// A call at this address, whose callee may set the flags.
+    Call(usize),
+}
+
+pub fn sets_flags(code: u32) -> bool {
+    OPCODES
+        .get(&(code >> 25))
+        .is_some_and(|instr| matches!(instr.name(), "cmp" | "cmpi"))
+}
+
+/// Predicated words depend on the flags of the last `cmp`/`cmpi`.
+///
+/// Warns when, on some path, that comparison is missing, happened in the caller or
+/// before a call that may compare again, or differs between the paths merging at a label.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Warning> {
+    let may_set = cfg.summarize(|addr| sets_flags(program.codes[addr]), |a, b| *a |= b);
+
+    let mut warnings = Vec::new();
+    for func in &cfg.functions {
+        let states = dataflow(program, cfg, func, |target| may_set[&target]);
+
+        for (addr, state) in states.into_iter().enumerate() {
+            let Some(state) = state else {
+                continue;
+            };
+            if !cfg.conditional(addr) {
+                continue;
+            }
+
+            let mut cmps = Vec::new();
+            for source in state {
+                let message = match source {
+                    Source::Missing => "no comparison sets the flags on some path".to_string(),
+                    Source::Caller => format!("the flags come from the caller of '{}'", func.name),
+                    Source::Call(call) => format!(
+                        "the flags may have been changed by the call at {}",
+                        program.location(call)
+                    ),
+                    Source::Cmp(cmp) => {
+                        cmps.push(program.source_map[cmp].0 + 1);
+                        continue;
+                    }
+                };
+                warnings.push(Warning::at(program, addr, message));
+            }
+
+            cmps.sort();
+            cmps.dedup();
+            if cmps.len() > 1 {
+                let lines = cmps.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+                warnings.push(Warning::at(
+                    program,
+                    addr,
+                    format!(
+                        "the flags may come from the comparisons at lines {}",
+                        lines.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// The sources of the flags on entry of each address of `func`.
+fn dataflow(
+    program: &Program,
+    cfg: &Cfg,
+    func: &Function,
+    may_set: impl Fn(usize) -> bool,
+) -> Vec<Option<BTreeSet<Source>>> {
+    let mut states = vec![None; program.codes.len()];
+    states[func.entry] = Some(BTreeSet::from([if func.entry == 0 {
+        Source::Missing
+    } else {
+        Source::Caller
+    }]));
+    let mut worklist = vec![func.entry];
+
+    while let Some(addr) = worklist.pop() {
+        let Some(state) = states[addr].clone() else {
+            continue;
+        };
+
+        let mut out = match cfg.flow(addr) {
+            _ if sets_flags(program.codes[addr]) => BTreeSet::from([Source::Cmp(addr)]),
+            Flow::Call(target) if target < program.codes.len() && may_set(target) => {
+                BTreeSet::from([Source::Call(addr)])
+            }
+            _ => state.clone(),
+        };
+        if cfg.conditional(addr) {
+            out.extend(state.iter().copied());
+        }
+
+        for succ in cfg.successors(addr) {
+            let merged = match &states[succ] {
+                Some(old) if out.is_subset(old) => continue,
+                Some(old) => old.union(&out).copied().collect(),
+                None => out.clone(),
+            };
+            states[succ] = Some(merged);
+            worklist.push(succ);
+        }
+    }
+
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn flags() {
+        let program = assemble(
+            "
+            main:
+                mv.eq r1 r2
+                cmp r1 0
+                add.eq r1 r1 1
+                call compare
+                mv.ne r2 r1
+                call quiet
+                cmp r2 1
+                call quiet
+                mv.gt r3 r2
+                jmp.eq skip
+                cmp r3 2
+            skip:
+                li.lt r4 1
+            halt:
+                jmp halt
+
+            compare:
+                cmp r1 r2
+                ret
+
+            quiet:
+                ret.eq
+                inc r5
+                ret
+            ",
+        );
+        let warnings = check(&program, &Cfg::new(&program));
+
+        assert_snapshot!(fmt_warnings(&warnings), @r"
+        Warning at line 3: 'mv.eq r1 r2' (no comparison sets the flags on some path)
+        Warning at line 7: 'mv.ne r2 r1' (the flags may have been changed by the call at line 6: 'call compare')
+        Warning at line 15: 'li.lt r4 1' (the flags may come from the comparisons at lines 9, 13)
+        Warning at line 24: 'ret.eq' (the flags come from the caller of 'quiet')
+        ");
+    }
+}
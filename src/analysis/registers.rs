@@ -0,0 +1,329 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    analysis::{
+        TRACKED, Warning,
+        cfg::{Cfg, Flow},
+        tracked,
+    },
+    assembler::Program,
+    instructions::{OPCODES, fmt_reg, parse_reg_s, reg_usage},
+};
+
+/// A register and the name it was written through.
+type Def = (u32, usize);
+
+struct Word {
+    /// Whether the word is a `push`, which may save a register the caller never wrote.
+    saves: bool,
+    /// Whether the word is a `pop`, which must balance the stack even if its value is unused.
+    restores: bool,
+    reads: Vec<u32>,
+    /// Registers written by the word, with the name used in the source.
+    writes: Vec<Def>,
+    /// The names used for each register in the source line.
+    names: HashMap<u32, Vec<usize>>,
+}
+
+struct Analysis<'a> {
+    program: &'a Program,
+    cfg: &'a Cfg<'a>,
+    words: Vec<Word>,
+    /// Names of registers as written in the source, either a constant or the register itself.
+    names: Vec<String>,
+}
+
+/// Warns about reads of registers that were never written, about a register read through
+/// a different `const` alias than the one it was written through, and about writes that
+/// are never read.
+///
+/// Calls are summarized by the registers their callees may read and write, and every
+/// register is assumed to be read after a `ret`. A `push` of a register never written is
+/// not reported, it may save a register of the caller, and neither is a `pop` of a value
+/// never read, it restores the register of a `push`.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Warning> {
+    let mut analysis = Analysis {
+        program,
+        cfg,
+        words: Vec::new(),
+        names: Vec::new(),
+    };
+    analysis.words = (0..program.codes.len())
+        .map(|addr| analysis.word(addr))
+        .collect();
+
+    let mut warnings = analysis.check_reads();
+    warnings.extend(analysis.check_writes());
+    warnings
+}
+
+impl Analysis<'_> {
+    fn word(&mut self, addr: usize) -> Word {
+        let (reads, writes) = reg_usage(self.program.codes[addr]).unwrap_or_default();
+
+        let mut names: HashMap<u32, Vec<usize>> = HashMap::new();
+        let (line, source) = &self.program.source_map[addr];
+        let tokens = source.split(|c: char| c.is_whitespace() || c == ',');
+        for token in tokens.filter(|t| !t.is_empty()).skip(1) {
+            if let Ok(reg) = parse_reg_s(&self.program.resolve(*line, token).into())
+                && tracked(reg)
+            {
+                let name = self.intern(token);
+                let entry = names.entry(reg).or_default();
+                if !entry.contains(&name) {
+                    entry.push(name);
+                }
+            }
+        }
+
+        let writes = writes
+            .into_iter()
+            .filter(|&reg| tracked(reg))
+            .flat_map(|reg| match names.get(&reg) {
+                Some(names) => names.iter().map(|&name| (reg, name)).collect(),
+                None => vec![(reg, self.intern(fmt_reg(reg)))],
+            })
+            .collect();
+
+        let code = self.program.codes[addr];
+        let name = OPCODES.get(&(code >> 25)).map(|i| i.name());
+        Word {
+            saves: name == Some("push"),
+            restores: name == Some("pop"),
+            reads: reads.into_iter().filter(|&reg| tracked(reg)).collect(),
+            writes,
+            names,
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+
+    fn is_const(&self, name: usize) -> bool {
+        self.program.constants.contains_key(&self.names[name])
+    }
+
+    /// Forward analysis of the names each register may have been written through.
+    fn check_reads(&self) -> Vec<Warning> {
+        let len = self.words.len();
+        let may_write = self.cfg.summarize(
+            |addr| {
+                self.words[addr]
+                    .writes
+                    .iter()
+                    .copied()
+                    .collect::<BTreeSet<Def>>()
+            },
+            |a, b| a.extend(b.iter().copied()),
+        );
+
+        let mut states: Vec<Option<BTreeSet<Def>>> = vec![None; len];
+        let mut worklist = Vec::new();
+        if len > 0 {
+            states[0] = Some(BTreeSet::new());
+            worklist.push(0);
+        }
+
+        while let Some(addr) = worklist.pop() {
+            let Some(state) = states[addr].clone() else {
+                continue;
+            };
+            let word = &self.words[addr];
+
+            let mut out = state.clone();
+            if !self.cfg.conditional(addr) {
+                out.retain(|(reg, _)| !word.writes.iter().any(|(r, _)| r == reg));
+            }
+            out.extend(word.writes.iter().copied());
+
+            let mut succs = self
+                .cfg
+                .successors(addr)
+                .into_iter()
+                .map(|succ| (succ, out.clone()))
+                .collect::<Vec<_>>();
+            if let Flow::Call(target) = self.cfg.flow(addr)
+                && target < len
+            {
+                succs.push((target, out.clone()));
+                for (succ, state) in &mut succs {
+                    if *succ == addr + 1 {
+                        state.extend(may_write[&target].iter().copied());
+                    }
+                }
+            }
+
+            for (succ, state) in succs {
+                let merged = match &states[succ] {
+                    Some(old) if state.is_subset(old) => continue,
+                    Some(old) => old.union(&state).copied().collect(),
+                    None => state,
+                };
+                states[succ] = Some(merged);
+                worklist.push(succ);
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for (addr, word) in self.words.iter().enumerate() {
+            let Some(state) = &states[addr] else {
+                continue;
+            };
+
+            for &reg in &word.reads {
+                let names = word.names.get(&reg).cloned().unwrap_or_default();
+                let writers = state
+                    .iter()
+                    .filter(|(r, _)| *r == reg)
+                    .map(|&(_, name)| name)
+                    .collect::<Vec<_>>();
+
+                if writers.is_empty() && !word.saves {
+                    let name = names
+                        .first()
+                        .map_or(fmt_reg(reg), |&n| self.names[n].as_str());
+                    warnings.push(Warning::at(
+                        self.program,
+                        addr,
+                        format!("'{}' is read before it is ever written", name),
+                    ));
+                    continue;
+                }
+
+                for &name in names.iter().filter(|&&n| self.is_const(n)) {
+                    for &writer in &writers {
+                        if !names.contains(&writer) && self.is_const(writer) {
+                            warnings.push(Warning::at(
+                                self.program,
+                                addr,
+                                format!(
+                                    "'{}' reads the value written through '{}', both are '{}'",
+                                    self.names[name],
+                                    self.names[writer],
+                                    fmt_reg(reg)
+                                ),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Backward liveness, as bit masks of registers.
+    fn check_writes(&self) -> Vec<Warning> {
+        let len = self.words.len();
+        let mask = |regs: &mut dyn Iterator<Item = u32>| regs.fold(0u32, |m, r| m | 1 << r);
+
+        let may_read = self.cfg.summarize(
+            |addr| mask(&mut self.words[addr].reads.iter().copied()),
+            |a, b| *a |= b,
+        );
+
+        let mut live_in = vec![0u32; len];
+        let live_out = |live_in: &[u32], addr: usize| -> u32 {
+            let mut out = match self.cfg.flow(addr) {
+                Flow::Ret => TRACKED,
+                _ => 0,
+            };
+            for succ in self.cfg.successors(addr) {
+                out |= live_in[succ];
+            }
+            if let Flow::Call(target) = self.cfg.flow(addr)
+                && let Some(reads) = may_read.get(&target)
+            {
+                out |= reads;
+            }
+            out
+        };
+
+        loop {
+            let mut changed = false;
+            for addr in (0..len).rev() {
+                let word = &self.words[addr];
+                let mut live = live_out(&live_in, addr);
+                if !self.cfg.conditional(addr) {
+                    live &= !mask(&mut word.writes.iter().map(|&(r, _)| r));
+                }
+                live |= mask(&mut word.reads.iter().copied());
+
+                if live != live_in[addr] {
+                    live_in[addr] = live;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for (addr, word) in self.words.iter().enumerate() {
+            if word.restores {
+                continue;
+            }
+            let out = live_out(&live_in, addr);
+            for &(reg, name) in &word.writes {
+                if out & (1 << reg) == 0 {
+                    warnings.push(Warning::at(
+                        self.program,
+                        addr,
+                        format!("the value written to '{}' is never read", self.names[name]),
+                    ));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn registers() {
+        let program = assemble(
+            "
+            const x r1
+            const y r2
+            const count r1
+
+            main:
+                li x 5
+                add r3 x r4
+                call work
+                mv io r3
+                push r5
+                pop r5
+                li y 1
+                li y 2
+                mv io y
+            halt:
+                jmp halt
+
+            work:
+                add r3 r3 count
+                ret
+            ",
+        );
+        let warnings = check(&program, &Cfg::new(&program));
+
+        assert_snapshot!(fmt_warnings(&warnings), @r"
+        Warning at line 8: 'add r3 x r4' ('r4' is read before it is ever written)
+        Warning at line 13: 'li y 1' (the value written to 'y' is never read)
+        Warning at line 20: 'add r3 r3 count' ('count' reads the value written through 'x', both are 'r1')
+        ");
+    }
+}
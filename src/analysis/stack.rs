@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{
+        Warning,
+        cfg::{Cfg, Flow, Function},
+    },
+    assembler::Program,
+    instructions::OPCODES,
+    utils::align_tabbed_lines,
+};
+
+/// Depths beyond this are treated as unbounded.
+const UNBOUNDED: i64 = i64::MAX / 4;
+
+/// After this many updates of the same address, growing bounds are widened to unbounded.
+const WIDEN_AFTER: usize = 8;
+
+/// The possible depths of the stack relative to the entry of a function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Interval {
+    lo: i64,
+    hi: i64,
+}
+
+impl Interval {
+    const ZERO: Interval = Interval { lo: 0, hi: 0 };
+
+    fn hull(self, other: Interval) -> Interval {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    fn shift(self, delta: i64) -> Interval {
+        let shift = |n: i64| {
+            if n.abs() >= UNBOUNDED { n } else { n + delta }
+        };
+        Interval {
+            lo: shift(self.lo),
+            hi: shift(self.hi),
+        }
+    }
+}
+
+/// Stack usage of a function found by walking its body, without its callees.
+struct Local {
+    max: i64,
+    /// `(callee, depth at the call)` of each call.
+    calls: Vec<(usize, Interval)>,
+    /// `ret`s reached with a depth other than zero.
+    unbalanced: Vec<(usize, Interval)>,
+    /// `pop`s that may go below the depth on entry.
+    underflows: Vec<usize>,
+}
+
+/// The stack usage of a function, including its callees.
+pub struct FunctionStack {
+    pub name: String,
+    pub entry: usize,
+    /// Maximum depth of the data stack pushed by the function itself, `None` if unbounded.
+    pub local: Option<u32>,
+    /// Maximum depth of the data stack including callees, `None` if unbounded.
+    pub total: Option<u32>,
+    /// Maximum depth of the call stack below the function, `None` if unbounded.
+    pub calls: Option<u32>,
+    pub recursive: bool,
+    unbalanced: Vec<(usize, Interval)>,
+    underflows: Vec<usize>,
+}
+
+/// Maximum stack depths of each function along all paths of the CFG.
+///
+/// Callees are assumed to leave the stack as they found it, functions that don't are
+/// reported as unbalanced. Recursion makes the depths of every function on the cycle,
+/// and of their callers, unbounded.
+pub struct StackAnalysis<'a> {
+    program: &'a Program,
+    pub functions: Vec<FunctionStack>,
+}
+
+impl<'a> StackAnalysis<'a> {
+    pub fn new(program: &'a Program, cfg: &Cfg) -> Self {
+        let locals = cfg
+            .functions
+            .iter()
+            .map(|func| (func.entry, local(program, cfg, func)))
+            .collect::<HashMap<_, _>>();
+
+        let mut walker = Walker {
+            locals: &locals,
+            done: HashMap::new(),
+            path: Vec::new(),
+        };
+        for func in &cfg.functions {
+            walker.walk(func.entry);
+        }
+
+        let functions = cfg
+            .functions
+            .iter()
+            .map(|func| {
+                let local = &locals[&func.entry];
+                let &(total, calls, recursive) = &walker.done[&func.entry];
+                FunctionStack {
+                    name: func.name.clone(),
+                    entry: func.entry,
+                    local: bounded(local.max),
+                    total: total.and_then(bounded),
+                    calls: calls.and_then(bounded),
+                    recursive,
+                    unbalanced: local.unbalanced.clone(),
+                    underflows: local.underflows.clone(),
+                }
+            })
+            .collect();
+
+        StackAnalysis { program, functions }
+    }
+
+    /// Warnings for unbalanced returns and pops below the depth on entry.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+
+        for func in &self.functions {
+            for &(addr, depth) in &func.unbalanced {
+                warnings.push(Warning::at(
+                    self.program,
+                    addr,
+                    format!(
+                        "'{}' may return with stack depth {} instead of 0",
+                        func.name,
+                        fmt_interval(depth)
+                    ),
+                ));
+            }
+            for &addr in &func.underflows {
+                warnings.push(Warning::at(
+                    self.program,
+                    addr,
+                    format!("'{}' may pop more than it pushed", func.name),
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// A table of the functions, followed by the depths of the whole program.
+    pub fn report(&self) -> String {
+        let mut rows = vec!["function\tstack\twith calls\tcall depth\tnotes".to_string()];
+
+        for func in &self.functions {
+            let mut notes = Vec::new();
+            if func.recursive {
+                notes.push("recursive".to_string());
+            }
+            for &(addr, depth) in &func.unbalanced {
+                notes.push(format!(
+                    "returns with {} at line {}",
+                    fmt_interval(depth),
+                    self.program.source_map[addr].0 + 1
+                ));
+            }
+            for &addr in &func.underflows {
+                notes.push(format!(
+                    "pops below entry at line {}",
+                    self.program.source_map[addr].0 + 1
+                ));
+            }
+
+            rows.push(format!(
+                "{}\t{}\t{}\t{}\t{}",
+                func.name,
+                fmt_depth(func.local),
+                fmt_depth(func.total),
+                fmt_depth(func.calls),
+                notes.join(", ")
+            ));
+        }
+
+        let mut report = align_tabbed_lines(&rows).collect::<Vec<_>>().join("\n");
+
+        if let Some(entry) = self.functions.iter().find(|f| f.entry == 0) {
+            report += &format!(
+                "\n\nprogram: stack depth {}, call depth {}\n",
+                fmt_depth(entry.total),
+                fmt_depth(entry.calls)
+            );
+        }
+
+        report
+    }
+}
+
+/// Walks the call graph depth-first, combining the local depths with those of the callees.
+struct Walker<'a> {
+    locals: &'a HashMap<usize, Local>,
+    /// `(total, calls, recursive)` of each finished function, `None` if unbounded.
+    done: HashMap<usize, (Option<i64>, Option<i64>, bool)>,
+    path: Vec<usize>,
+}
+
+impl Walker<'_> {
+    fn walk(&mut self, func: usize) -> (Option<i64>, Option<i64>) {
+        if let Some(&(total, calls, _)) = self.done.get(&func) {
+            return (total, calls);
+        }
+
+        if let Some(pos) = self.path.iter().position(|&f| f == func) {
+            for &f in &self.path[pos..] {
+                self.done.insert(f, (None, None, true));
+            }
+            return (None, None);
+        }
+
+        let local = &self.locals[&func];
+        self.path.push(func);
+
+        let mut total = Some(local.max);
+        let mut calls = Some(0);
+        for &(target, depth) in &local.calls {
+            let (callee_total, callee_calls) = self.walk(target);
+            total = match (total, callee_total) {
+                (Some(total), Some(callee)) if depth.hi < UNBOUNDED => {
+                    Some(total.max(depth.hi + callee))
+                }
+                _ => None,
+            };
+            calls = calls.zip(callee_calls).map(|(c, callee)| c.max(callee + 1));
+        }
+
+        self.path.pop();
+
+        let recursive = self.done.get(&func).is_some_and(|&(_, _, r)| r);
+        if recursive {
+            (total, calls) = (None, None);
+        }
+        self.done.insert(func, (total, calls, recursive));
+
+        (total, calls)
+    }
+}
+
+fn local(program: &Program, cfg: &Cfg, func: &Function) -> Local {
+    let len = program.codes.len();
+    let mut states: Vec<Option<Interval>> = vec![None; len];
+    let mut updates = vec![0; len];
+    let mut worklist = vec![func.entry];
+    states[func.entry] = Some(Interval::ZERO);
+
+    let mut max = 0;
+    let mut calls = Vec::new();
+    let mut unbalanced = Vec::new();
+    let mut underflows = Vec::new();
+
+    while let Some(addr) = worklist.pop() {
+        let Some(state) = states[addr] else {
+            continue;
+        };
+
+        let code = program.codes[addr];
+        let delta = match OPCODES.get(&(code >> 25)).map(|i| i.name()) {
+            Some("push" | "pushi") => 1,
+            Some("pop") => -1,
+            _ => 0,
+        };
+        let mut out = state.shift(delta);
+        if cfg.conditional(addr) {
+            out = out.hull(state);
+        }
+        max = max.max(out.hi);
+
+        for succ in cfg.successors(addr) {
+            let merged = match states[succ] {
+                Some(old) => {
+                    let mut merged = old.hull(out);
+                    if merged == old {
+                        continue;
+                    }
+
+                    updates[succ] += 1;
+                    if updates[succ] > WIDEN_AFTER {
+                        if merged.hi > old.hi {
+                            merged.hi = UNBOUNDED;
+                        }
+                        if merged.lo < old.lo {
+                            merged.lo = -UNBOUNDED;
+                        }
+                    }
+                    merged
+                }
+                None => out,
+            };
+            states[succ] = Some(merged);
+            worklist.push(succ);
+        }
+    }
+
+    for addr in 0..len {
+        let Some(state) = states[addr] else {
+            continue;
+        };
+
+        match cfg.flow(addr) {
+            Flow::Call(target) if target < len => calls.push((target, state)),
+            Flow::Ret if state != Interval::ZERO => unbalanced.push((addr, state)),
+            _ => {}
+        }
+        if state.lo <= 0
+            && OPCODES
+                .get(&(program.codes[addr] >> 25))
+                .is_some_and(|i| i.name() == "pop")
+        {
+            underflows.push(addr);
+        }
+    }
+
+    Local {
+        max,
+        calls,
+        unbalanced,
+        underflows,
+    }
+}
+
+fn bounded(depth: i64) -> Option<u32> {
+    (depth < UNBOUNDED).then_some(depth.max(0) as u32)
+}
+
+fn fmt_depth(depth: Option<u32>) -> String {
+    depth.map_or("unbounded".to_string(), |d| d.to_string())
+}
+
+fn fmt_interval(depth: Interval) -> String {
+    let fmt = |n: i64| {
+        if n >= UNBOUNDED {
+            "inf".to_string()
+        } else if n <= -UNBOUNDED {
+            "-inf".to_string()
+        } else {
+            n.to_string()
+        }
+    };
+
+    if depth.lo == depth.hi {
+        fmt(depth.lo)
+    } else {
+        format!("{}..{}", fmt(depth.lo), fmt(depth.hi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn stack() {
+        let program = assemble(
+            "
+            main:
+                push r1
+                call twice
+                call leaky
+                call rec
+                cmp r1 9
+                call.eq grow
+                pop r1
+            halt:
+                jmp halt
+
+            twice:
+                pushi 1
+                pushi 2
+                call nested
+                pop r1
+                pop r1
+                ret
+
+            nested:
+                push r1
+                push r2
+                pop r2
+                pop r1
+                pop r3
+                ret
+
+            leaky:
+                cmp r1 0
+                pushi.eq 3
+                ret
+
+            rec:
+                cmp r1 0
+                ret.eq
+                dec r1
+                call rec
+                ret
+
+            grow:
+                push r1
+                jmp grow
+            ",
+        );
+        let cfg = Cfg::new(&program);
+        let analysis = StackAnalysis::new(&program, &cfg);
+
+        assert_snapshot!(analysis.report(), @r"
+        function  stack      with calls  call depth  notes
+        main      1          unbounded   unbounded
+        twice     2          4           1
+        nested    2          2           0           returns with -1 at line 27, pops below entry at line 26
+        leaky     1          1           0           returns with 0..1 at line 32
+        rec       0          unbounded   unbounded   recursive
+        grow      unbounded  unbounded   0
+
+        program: stack depth unbounded, call depth unbounded
+        ");
+
+        assert_snapshot!(fmt_warnings(&analysis.warnings()), @r"
+        Warning at line 26: 'pop r3' ('nested' may pop more than it pushed)
+        Warning at line 27: 'ret' ('nested' may return with stack depth -1 instead of 0)
+        Warning at line 32: 'ret' ('leaky' may return with stack depth 0..1 instead of 0)
+        ");
+    }
+}
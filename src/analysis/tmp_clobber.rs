@@ -0,0 +1,250 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::{
+        Warning,
+        cfg::{Cfg, Flow},
+    },
+    assembler::Program,
+    instructions::{INSTRUCTIONS, parse_reg_s, reg_usage},
+    operand::{OperandType, OperandValue},
+};
+
+const TMP: u32 = 31;
+
+/// What may be in `tmp` at an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Fact {
+    /// A value written by the user.
+    User,
+    /// The value of the user was overwritten by the expansion starting at this address.
+    Clobbered(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Write {
+    User,
+    Macro(usize),
+}
+
+#[derive(Debug, Default)]
+struct Word {
+    user_read: bool,
+    write: Option<Write>,
+}
+
+/// Macro-instructions load large immediates through `tmp`, which silently replaces
+/// whatever the user kept there.
+///
+/// Warns when a value the user wrote to `tmp` is read after an expansion overwrote it,
+/// and when a macro operand is `tmp` but the expansion overwrites it before reading it.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let mut words = Vec::with_capacity(program.codes.len());
+
+    let mut start = 0;
+    while start < program.codes.len() {
+        let line = program.source_map[start].0;
+        let end = (start..program.codes.len())
+            .find(|&a| program.source_map[a].0 != line)
+            .unwrap_or(program.codes.len());
+
+        if program.expanded[start] {
+            let (dest_tmp, src_tmp) = tmp_operands(program, start);
+            let mut written = false;
+            let mut warned = false;
+
+            for &code in &program.codes[start..end] {
+                let (reads, writes) = reg_usage(code).unwrap_or_default();
+                let mut word = Word::default();
+
+                if reads.contains(&TMP) && src_tmp {
+                    if !written {
+                        word.user_read = true;
+                    } else if !warned {
+                        warnings.push(Warning::at(
+                            program,
+                            start,
+                            "operand 'tmp' is overwritten by the macro expansion before it is read"
+                                .to_string(),
+                        ));
+                        warned = true;
+                    }
+                }
+                if writes.contains(&TMP) {
+                    written = true;
+                    word.write = Some(if dest_tmp {
+                        Write::User
+                    } else {
+                        Write::Macro(start)
+                    });
+                }
+
+                words.push(word);
+            }
+        } else {
+            for &code in &program.codes[start..end] {
+                let (reads, writes) = reg_usage(code).unwrap_or_default();
+                words.push(Word {
+                    user_read: reads.contains(&TMP),
+                    write: writes.contains(&TMP).then_some(Write::User),
+                });
+            }
+        }
+
+        start = end;
+    }
+
+    let states = dataflow(cfg, &words);
+
+    for (addr, word) in words.iter().enumerate() {
+        if !word.user_read {
+            continue;
+        }
+
+        for fact in &states[addr] {
+            if let Fact::Clobbered(by) = *fact {
+                warnings.push(Warning::at(
+                    program,
+                    addr,
+                    format!(
+                        "'tmp' was overwritten by the macro expansion at {}",
+                        program.location(by)
+                    ),
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// Whether the source line of `addr` names `tmp` as its destination and as one of its sources.
+fn tmp_operands(program: &Program, addr: usize) -> (bool, bool) {
+    let (line, source) = &program.source_map[addr];
+    let tokens = source.split_whitespace().collect::<Vec<_>>();
+    let Some((&mnemonic, operands)) = tokens.split_first() else {
+        return (false, false);
+    };
+
+    let name = mnemonic.split('.').next().unwrap_or(mnemonic);
+    let types = INSTRUCTIONS
+        .get(name)
+        .or_else(|| name.strip_suffix('i').and_then(|n| INSTRUCTIONS.get(n)))
+        .map(|instr| instr.get_operand_types());
+
+    let (mut dest, mut src) = (false, false);
+    for (i, op) in operands.iter().enumerate() {
+        let op = OperandValue::from(program.resolve(*line, op));
+        if parse_reg_s(&op).ok() != Some(TMP) {
+            continue;
+        }
+
+        match types.and_then(|types| types.get(i)) {
+            Some(OperandType::RegD) => dest = true,
+            _ => src = true,
+        }
+    }
+
+    (dest, src)
+}
+
+/// The facts about `tmp` on entry of each address, reachable from address 0.
+///
+/// Calls continue into both the callee and the next word, as the callee sees the `tmp`
+/// of the caller.
+fn dataflow(cfg: &Cfg, words: &[Word]) -> Vec<BTreeSet<Fact>> {
+    let mut states = vec![BTreeSet::new(); words.len()];
+    let mut visited = vec![false; words.len()];
+    let mut worklist = vec![0];
+
+    while let Some(addr) = worklist.pop() {
+        if addr >= words.len() {
+            continue;
+        }
+        visited[addr] = true;
+
+        let state = &states[addr];
+        let word = &words[addr];
+
+        let mut out = match word.write {
+            Some(Write::User) => BTreeSet::from([Fact::User]),
+            Some(Write::Macro(by)) if !state.is_empty() => BTreeSet::from([Fact::Clobbered(by)]),
+            _ => state.clone(),
+        };
+        if cfg.conditional(addr) {
+            out.extend(state.iter().copied());
+        }
+
+        let mut succs = cfg.successors(addr);
+        if let Flow::Call(target) = cfg.flow(addr)
+            && target < words.len()
+        {
+            succs.push(target);
+        }
+
+        for succ in succs {
+            let before = states[succ].len();
+            states[succ].extend(out.iter().copied());
+            if !visited[succ] || states[succ].len() != before {
+                visited[succ] = true;
+                worklist.push(succ);
+            }
+        }
+    }
+
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    fn warnings(src: &str) -> String {
+        let program = assemble(src);
+        fmt_warnings(&check(&program, &Cfg::new(&program)))
+    }
+
+    #[test]
+    fn tmp_clobber() {
+        assert_snapshot!(warnings("
+            li tmp 5
+            add r1 r2 0x1234
+            mv r3 tmp
+        "), @"Warning at line 4: 'mv r3 tmp' ('tmp' was overwritten by the macro expansion at line 3: 'add r1 r2 0x1234')");
+
+        assert_snapshot!(warnings("
+            const scratch tmp
+            li scratch 5
+            cmp r1 r2
+            li.eq r1 0x1234
+            add r3 scratch scratch
+        "), @"Warning at line 6: 'add r3 scratch scratch' ('tmp' was overwritten by the macro expansion at line 5: 'li.eq r1 0x1234')");
+
+        assert_snapshot!(warnings("
+            li tmp 5
+            loop:
+                add r1 tmp 1
+                beqi r1 9 loop
+        "), @"Warning at line 4: 'add r1 tmp 1' ('tmp' was overwritten by the macro expansion at line 5: 'beqi r1 9 loop')");
+
+        assert_snapshot!(warnings("
+            li tmp 1
+            addi r1 tmp 0x1234
+            beqi tmp 3 0
+        "), @r"
+        Warning at line 3: 'addi r1 tmp 0x1234' (operand 'tmp' is overwritten by the macro expansion before it is read)
+        Warning at line 4: 'beqi tmp 3 0' (operand 'tmp' is overwritten by the macro expansion before it is read)
+        ");
+
+        // Writing `tmp` again, or through a macro that targets it, is fine.
+        assert_snapshot!(warnings("
+            li tmp 5
+            add r1 r2 0x1234
+            li tmp 0x5678
+            mv r3 tmp
+            add r4 tmp 3
+        "), @"");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    analysis::{
+        Warning,
+        cfg::{Cfg, Flow},
+    },
+    assembler::Program,
+};
+
+/// Warns about constants never substituted, labels never referenced by an operand,
+/// and code that can't be reached from address 0 or any label.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    for name in program.constants.keys() {
+        if !program.used_constants.contains(name) {
+            warnings.push(Warning::at_definition(
+                program,
+                name,
+                format!("constant '{}' is never used", name),
+            ));
+        }
+    }
+
+    for (name, &addr) in &program.labels {
+        // The label of the entry is implicitly used.
+        if addr != 0 && !program.referenced_labels.contains(name) {
+            warnings.push(Warning::at_definition(
+                program,
+                name,
+                format!("label '{}' is never referenced", name),
+            ));
+        }
+    }
+
+    let reachable = reachable(program, cfg);
+    let mut addr = 0;
+    while addr < reachable.len() {
+        if reachable[addr] {
+            addr += 1;
+            continue;
+        }
+
+        let end = (addr..reachable.len())
+            .find(|&a| reachable[a])
+            .unwrap_or(reachable.len());
+        let words = end - addr;
+        warnings.push(Warning::at(
+            program,
+            addr,
+            format!(
+                "{} word{} of unreachable code",
+                words,
+                if words == 1 { "" } else { "s" }
+            ),
+        ));
+        addr = end;
+    }
+
+    warnings
+}
+
+fn reachable(program: &Program, cfg: &Cfg) -> Vec<bool> {
+    let len = program.codes.len();
+    let mut reachable = vec![false; len];
+
+    let mut worklist = vec![0];
+    worklist.extend(program.labels.right_values().copied());
+
+    while let Some(addr) = worklist.pop() {
+        if addr >= len || reachable[addr] {
+            continue;
+        }
+        reachable[addr] = true;
+
+        worklist.extend(cfg.successors(addr));
+        if let Flow::Call(target) = cfg.flow(addr) {
+            worklist.push(target);
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn unused() {
+        let program = assemble(
+            "
+            const COUNT 3
+            const UNUSED 5
+            const step r2
+
+            main:
+                li r1 COUNT
+            loop:
+                add r1 r1 step
+                jmp loop
+                li r3 1
+                li r4 2
+            stale:
+                ret
+            ",
+        );
+        let warnings = check(&program, &Cfg::new(&program));
+
+        assert_snapshot!(fmt_warnings(&warnings), @r"
+        Warning at line 3: 'const UNUSED 5' (constant 'UNUSED' is never used)
+        Warning at line 11: 'li r3 1' (2 words of unreachable code)
+        Warning at line 13: 'stale:' (label 'stale' is never referenced)
+        ");
+
+        let program = assemble("main:\n  jmp main\n  inc r1");
+        let warnings = check(&program, &Cfg::new(&program));
+        assert_snapshot!(fmt_warnings(&warnings), @"Warning at line 3: 'inc r1' (1 word of unreachable code)");
+    }
+}
@@ -1,6 +1,18 @@
-use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
 
-use crate::{pass1::Pass1, pass2::Pass2};
+use anyhow::{Result, anyhow};
+use bimap::BiHashMap;
+
+use crate::{
+    analysis::{Warning, analyze},
+    pass1::Pass1,
+    pass2::Pass2,
+    signature::Signature,
+    utils::{align_tabbed_lines, fmt_hex},
+};
 
 pub struct Assembler {
     settings: AssemblerSettings,
@@ -9,6 +21,32 @@ pub struct Assembler {
 
 pub struct AssemblerSettings {
     pub disable_macro: bool,
+    /// Constants defined before the first line, as `NAME, VALUE`.
+    pub defines: Vec<(String, String)>,
+}
+
+/// The result of assembling a source file.
+pub struct Program {
+    pub codes: Vec<u32>,
+    pub displays: Vec<String>,
+    pub labels: BiHashMap<String, usize>,
+    /// The value of each constant, the last one if it is defined in several scopes.
+    pub constants: HashMap<String, String>,
+    /// The constants defined before the first label, as seen from test blocks.
+    pub global_constants: HashMap<String, String>,
+    /// The constants substituted on each line, by line index.
+    pub substitutions: HashMap<usize, HashMap<String, String>>,
+    /// The index and content of the line defining each constant and label.
+    pub definitions: HashMap<String, (usize, String)>,
+    pub used_constants: HashSet<String>,
+    pub referenced_labels: HashSet<String>,
+    /// The `.func` signatures of labels.
+    pub signatures: HashMap<String, Signature>,
+    /// Maps each address to the index and content of its original source line.
+    pub source_map: Vec<(usize, String)>,
+    /// Whether each address comes from a macro expansion.
+    pub expanded: Vec<bool>,
+    pub warnings: Vec<Warning>,
 }
 
 impl Assembler {
@@ -19,11 +57,162 @@ impl Assembler {
         }
     }
 
-    pub fn assemble(&self) -> Result<(Vec<u32>, Vec<String>)> {
+    pub fn assemble(&self) -> Result<Program> {
         let mut pass1 = Pass1::new(self.settings.disable_macro);
+        for (name, value) in &self.settings.defines {
+            pass1.define(name, value);
+        }
         pass1.run(&self.source_lines)?;
 
-        let pass2 = Pass2::new(pass1.labels, pass1.addr_to_original);
-        pass2.run(pass1.processed)
+        let labels = pass1
+            .labels
+            .iter()
+            .map(|(&name, &addr)| (name.to_string(), addr))
+            .collect();
+        let constants = pass1
+            .constants
+            .iter()
+            .map(|(&name, &value)| (name.to_string(), value.to_string()))
+            .collect();
+        let global_constants = pass1
+            .global_values()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let substitutions = pass1
+            .substitutions
+            .iter()
+            .map(|(&line, names)| {
+                let names = names
+                    .iter()
+                    .map(|(&name, value)| (name.to_string(), value.to_string()))
+                    .collect();
+                (line, names)
+            })
+            .collect();
+        let source_map = pass1
+            .addr_to_original
+            .iter()
+            .map(|&(idx, line)| (idx, line.to_string()))
+            .collect();
+
+        let definitions = pass1
+            .definitions
+            .iter()
+            .map(|(&name, &(idx, line))| (name.to_string(), (idx, line.to_string())))
+            .collect();
+        let used_constants = pass1.used_constants.iter().map(|s| s.to_string()).collect();
+        let expanded = pass1.expanded;
+        let signatures = pass1
+            .signatures
+            .into_iter()
+            .map(|(name, signature)| (name.to_string(), signature))
+            .collect();
+
+        let mut pass2 = Pass2::new(pass1.labels, pass1.generated_labels, pass1.addr_to_original);
+        let (codes, displays) = pass2.run(pass1.processed)?;
+        let referenced_labels = pass2
+            .referenced_labels
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let mut program = Program {
+            codes,
+            displays,
+            labels,
+            constants,
+            global_constants,
+            substitutions,
+            definitions,
+            used_constants,
+            referenced_labels,
+            signatures,
+            source_map,
+            expanded,
+            warnings: Vec::new(),
+        };
+        program.warnings = analyze(&program);
+
+        Ok(program)
+    }
+}
+
+impl Program {
+    /// Returns the addresses from the label `name` up to the next label.
+    pub fn label_range(&self, name: &str) -> Option<Range<usize>> {
+        let &start = self.labels.get_by_left(name)?;
+        let end = self
+            .labels
+            .right_values()
+            .copied()
+            .filter(|&addr| addr > start)
+            .min()
+            .unwrap_or(self.codes.len());
+
+        Some(start..end)
+    }
+
+    /// Substitutes `name` as it was on the line `line`, if it is a constant there.
+    pub fn resolve<'b>(&'b self, line: usize, name: &'b str) -> &'b str {
+        self.substitutions
+            .get(&line)
+            .and_then(|names| names.get(name))
+            .map_or(name, |value| value.as_str())
+    }
+
+    /// Substitutes `name` if it is a constant defined before the first label.
+    pub fn resolve_global<'b>(&'b self, name: &'b str) -> &'b str {
+        self.global_constants
+            .get(name)
+            .map_or(name, |value| value.as_str())
+    }
+
+    /// Names `addr` by its label, the unlabeled entry of the program is `<entry>`.
+    pub fn symbol(&self, addr: usize) -> String {
+        match self.labels.get_by_right(&addr) {
+            Some(label) => label.clone(),
+            None if addr == 0 => "<entry>".to_string(),
+            None => fmt_hex(addr as u32),
+        }
+    }
+
+    /// Lists the labels by address, with their signatures.
+    pub fn symbols(&self) -> String {
+        let mut labels = self.labels.iter().collect::<Vec<_>>();
+        if labels.is_empty() {
+            return String::new();
+        }
+        labels.sort_by_key(|&(name, &addr)| (addr, name));
+
+        let lines = labels
+            .into_iter()
+            .map(|(name, &addr)| {
+                let signature = self
+                    .signatures
+                    .get(name)
+                    .map_or(String::new(), |s| s.to_string());
+                format!("0x{:04X}\t{}\t{}", addr, name, signature)
+            })
+            .collect::<Vec<_>>();
+
+        align_tabbed_lines(&lines)
+            .map(|line| line.trim_end().to_string() + "\n")
+            .collect()
+    }
+
+    /// Describes `addr` by its source line.
+    pub fn location(&self, addr: usize) -> String {
+        match self.source_map.get(addr) {
+            Some((idx, line)) => format!("line {}: '{}'", idx + 1, line),
+            None => fmt_hex(addr as u32),
+        }
+    }
+
+    /// Attaches the source line of `addr` to a runtime error.
+    pub fn error_at(&self, addr: usize, err: anyhow::Error) -> anyhow::Error {
+        match self.source_map.get(addr) {
+            Some((idx, line)) => anyhow!("Error at line {}: '{}' ({})", idx + 1, line, err),
+            None => err,
+        }
     }
 }
@@ -2,20 +2,30 @@ use std::{
     fmt::Display,
     fs::File,
     io::{Write, stdout},
+    ops::Range,
 };
 
 use anyhow::Result;
 use clap::{
-    Parser,
+    Args, Parser, Subcommand,
     ValueHint::FilePath,
     builder::{Styles, styling::AnsiColor},
 };
 use clap_complete::Shell;
 
+use crate::{
+    emulator::{MachineProfile, TraceFormat},
+    instructions::parse_imm,
+};
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(styles = get_styles())]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Print shell auto completions for the specified shell.
     #[arg(long, exclusive = true)]
     pub complete: Option<Shell>,
@@ -32,9 +42,172 @@ pub struct Cli {
     #[arg(long)]
     pub bin: bool,
 
+    /// Write the address and `.func` signature of each label to this file.
+    #[arg(long, value_hint = FilePath)]
+    pub symbols: Option<Output>,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+}
+
+/// The options of the assembler, shared by the commands assembling source files.
+#[derive(Args)]
+pub struct AssembleArgs {
     /// Disable the macro-instructions.
     #[arg(long)]
     pub disable_macro: bool,
+
+    /// Define a constant overriding its `const` in the source, e.g. `-D CHEAT=1`, `NAME`
+    /// alone is `NAME=1`.
+    #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = parse_define)]
+    pub define: Vec<(String, String)>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Assemble the source file and run it in the emulator.
+    Run(RunArgs),
+
+    /// Run the test blocks (`#> test NAME`) in the source files.
+    Test(TestArgs),
+
+    /// Export the control-flow graph of each function as Graphviz DOT.
+    Cfg(CfgArgs),
+
+    /// Report the maximum stack and call depth of each function.
+    Stack(StackArgs),
+
+    /// Check the source files against style and correctness rules.
+    Lint(LintArgs),
+
+    /// Format the source files in place.
+    Fmt(FmtArgs),
+
+    /// Start a language server speaking LSP over stdio.
+    Lsp,
+}
+
+#[derive(Args)]
+pub struct FmtArgs {
+    /// Source files, or directories to search for `.asm` files.
+    #[arg(value_hint = FilePath, required = true)]
+    pub paths: Vec<String>,
+
+    /// Only report the files that would change, failing if there are any.
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Args)]
+pub struct LintArgs {
+    /// Source files, or directories to search for `.asm` files.
+    #[arg(value_hint = FilePath, required = true)]
+    pub paths: Vec<String>,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+
+    /// A file setting the level of rules, with lines like `branch-to-next = deny`.
+    #[arg(long, value_hint = FilePath)]
+    pub config: Option<String>,
+}
+
+#[derive(Args)]
+pub struct StackArgs {
+    /// File path to the source assembly file.
+    #[arg(value_hint = FilePath)]
+    pub src_file: String,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+}
+
+#[derive(Args)]
+pub struct CfgArgs {
+    /// File path to the source assembly file.
+    #[arg(value_hint = FilePath)]
+    pub src_file: String,
+
+    /// The output file path.
+    #[arg(short, long, value_hint = FilePath, default_value_t = Output::Stdout)]
+    pub output: Output,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+
+    /// Only export the functions with these names.
+    #[arg(long)]
+    pub function: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// Source files, or directories to search for `.asm` files.
+    #[arg(value_hint = FilePath, required = true)]
+    pub paths: Vec<String>,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+
+    /// The default cycle limit of each test.
+    #[arg(long, default_value_t = 100_000)]
+    pub max_cycles: u64,
+
+    #[command(flatten)]
+    pub machine: MachineProfile,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// File path to the source assembly file.
+    #[arg(value_hint = FilePath)]
+    pub src_file: String,
+
+    #[command(flatten)]
+    pub assemble: AssembleArgs,
+
+    /// Values read from `io`, in order.
+    #[arg(long, value_delimiter = ',', value_parser = parse_num)]
+    pub input: Vec<u32>,
+
+    /// Key codes read from `kb`, in order.
+    #[arg(long, value_delimiter = ',', value_parser = parse_num)]
+    pub keys: Vec<u32>,
+
+    /// Seed of the random number generator.
+    #[arg(long, default_value_t = 1, value_parser = parse_num)]
+    pub seed: u32,
+
+    /// Stop after executing this many words, exiting with status 2, `0` for no limit.
+    #[arg(long, default_value_t = 1_000_000)]
+    pub max_cycles: u64,
+
+    #[command(flatten)]
+    pub machine: MachineProfile,
+
+    /// Write an execution trace to the file.
+    #[arg(long, value_hint = FilePath)]
+    pub trace: Option<Output>,
+
+    /// The format of the execution trace.
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    pub trace_format: TraceFormat,
+
+    /// Only trace addresses in the range, e.g. `0x10..0x20`.
+    #[arg(long, value_parser = parse_addr_range)]
+    pub trace_range: Vec<Range<usize>>,
+
+    /// Only trace the words from the label up to the next label.
+    #[arg(long)]
+    pub trace_label: Vec<String>,
+
+    /// Write a per-function and per-label profile to the file.
+    #[arg(long, value_hint = FilePath)]
+    pub profile: Option<Output>,
+
+    /// Write the listing annotated with executed and skipped counts to the file.
+    #[arg(long, value_hint = FilePath)]
+    pub profile_listing: Option<Output>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -73,6 +246,28 @@ impl From<&str> for Output {
     }
 }
 
+fn parse_num(s: &str) -> Result<u32, String> {
+    parse_imm(&s.into()).map_err(|e| e.to_string())
+}
+
+fn parse_define(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+    if name.is_empty() || name.contains(char::is_whitespace) || value.contains(char::is_whitespace)
+    {
+        return Err(format!("Expected 'NAME=VALUE', got '{}'", s));
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_addr_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("Expected a range like 'start..end', got '{}'", s))?;
+
+    Ok(parse_num(start)? as usize..parse_num(end)? as usize)
+}
+
 fn get_styles() -> Styles {
     Styles::styled()
         .header(AnsiColor::Yellow.on_default().bold().underline())
@@ -0,0 +1,588 @@
+mod halt;
+mod machine;
+mod profile;
+mod trace;
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use anyhow::{Result, anyhow, bail};
+
+pub use halt::Halt;
+pub use machine::MachineProfile;
+pub use profile::Profiler;
+pub use trace::{TraceFormat, Tracer};
+
+use crate::{
+    emulator::halt::{LoopDetector, mem_entry_hash},
+    instructions::{OPCODES, fmt_reg},
+    utils::{align_tabbed_lines, fmt_hex},
+};
+
+const REG_ZERO: u32 = 0;
+const REG_PC: u32 = 25;
+const REG_IO: u32 = 26;
+const REG_KB: u32 = 27;
+const REG_RNG: u32 = 28;
+
+pub struct EmulatorSettings {
+    /// Values read from `io`, in order.
+    pub inputs: Vec<u32>,
+    /// Key codes read from `kb`, in order.
+    pub keys: Vec<u32>,
+    /// Seed of the random number generator behind `rng`.
+    pub seed: u32,
+    pub machine: MachineProfile,
+}
+
+/// Executes assembled machine code.
+pub struct Emulator<'a> {
+    codes: &'a [u32],
+    machine: MachineProfile,
+    regs: [u32; 32],
+    pc: usize,
+    flags: Ordering,
+    color: u32,
+    memory: HashMap<u32, u32>,
+    /// Order independent hash of the memory, kept up to date on every store.
+    mem_hash: u64,
+    stack: Vec<u32>,
+    call_stack: Vec<usize>,
+    cycle: u64,
+    inputs: VecDeque<u32>,
+    keys: VecDeque<u32>,
+    rng: u32,
+    loops: LoopDetector,
+    halted: Option<Halt>,
+    pub outputs: Vec<u32>,
+}
+
+/// A single executed word and everything it did.
+pub struct Step {
+    pub cycle: u64,
+    pub pc: usize,
+    pub code: u32,
+    /// Whether the predicate passed, always `true` for unconditional instructions.
+    pub executed: bool,
+    pub effects: Vec<Effect>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// A read from `io`, `kb` or `rng`.
+    RegRead {
+        reg: u32,
+        value: u32,
+    },
+    RegWrite {
+        reg: u32,
+        value: u32,
+    },
+    Flags(Ordering),
+    MemRead {
+        addr: u32,
+        value: u32,
+    },
+    MemWrite {
+        addr: u32,
+        value: u32,
+    },
+    Push(u32),
+    Pop(u32),
+    Peek(u32),
+    Jump(usize),
+    Call {
+        target: usize,
+        ret: usize,
+    },
+    Ret(usize),
+    Color(u32),
+    Pixel {
+        x: u32,
+        y: u32,
+        color: u32,
+    },
+    Segment(u32),
+    Output(u32),
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(settings: EmulatorSettings, codes: &'a [u32]) -> Self {
+        Emulator {
+            codes,
+            machine: settings.machine,
+            regs: [0; 32],
+            pc: 0,
+            flags: Ordering::Equal,
+            color: 0,
+            memory: HashMap::new(),
+            mem_hash: 0,
+            stack: Vec::new(),
+            call_stack: Vec::new(),
+            cycle: 0,
+            inputs: settings.inputs.into(),
+            keys: settings.keys.into(),
+            rng: settings.seed.max(1),
+            loops: LoopDetector::new(),
+            halted: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn halted(&self) -> Option<Halt> {
+        self.halted
+    }
+
+    /// Reads a register without the side effects of reading a device.
+    pub fn reg(&self, reg: u32) -> u32 {
+        match reg {
+            REG_ZERO | REG_IO | REG_KB | REG_RNG => 0,
+            REG_PC => self.pc as u32,
+            _ => self.regs[reg as usize],
+        }
+    }
+
+    pub fn set_reg(&mut self, reg: u32, value: u32) {
+        self.write_reg(reg, value, &mut Vec::new());
+    }
+
+    pub fn mem(&self, addr: u32) -> u32 {
+        self.memory.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn set_mem(&mut self, addr: u32, value: u32) {
+        let old = self.memory.insert(addr, value).unwrap_or(0);
+        self.mem_hash ^= mem_entry_hash(addr, old) ^ mem_entry_hash(addr, value);
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Calls `target` as if from outside the program, it returns to the address
+    /// right after the last word.
+    pub fn call(&mut self, target: usize) {
+        self.call_stack.push(self.codes.len());
+        self.pc = target;
+    }
+
+    /// Executes the word at the current program counter.
+    pub fn step(&mut self) -> Result<Step> {
+        let pc = self.pc;
+        let code = *self
+            .codes
+            .get(pc)
+            .ok_or_else(|| anyhow!("Program counter out of program: {}", fmt_hex(pc as u32)))?;
+
+        let instr = OPCODES
+            .get(&(code >> 25))
+            .ok_or_else(|| anyhow!("Unknown opcode at {}: 0x{:08X}", fmt_hex(pc as u32), code))?;
+        let (cond, ops) = instr.decode(code);
+
+        let mut step = Step {
+            cycle: self.cycle,
+            pc,
+            code,
+            executed: self.check_cond(cond)?,
+            effects: Vec::new(),
+        };
+
+        self.cycle += 1;
+        self.pc += 1;
+
+        if step.executed {
+            self.execute(instr.name(), &ops, &mut step.effects)?;
+        }
+
+        for effect in &step.effects {
+            if let Effect::Jump(target) = *effect {
+                self.check_halt(pc, target);
+            }
+        }
+
+        Ok(step)
+    }
+
+    fn check_halt(&mut self, pc: usize, target: usize) {
+        if target == pc {
+            self.halted = Some(Halt::SelfJump);
+        } else if target < pc
+            && self.inputs.is_empty()
+            && self.keys.is_empty()
+            && self.loops.check(self.state_hash())
+        {
+            self.halted = Some(Halt::RepeatedState);
+        }
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.pc.hash(&mut hasher);
+        self.regs.hash(&mut hasher);
+        self.flags.hash(&mut hasher);
+        self.color.hash(&mut hasher);
+        self.mem_hash.hash(&mut hasher);
+        self.stack.hash(&mut hasher);
+        self.call_stack.hash(&mut hasher);
+        self.rng.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The program counter, flags, stacks and non-zero registers.
+    pub fn report(&self) -> String {
+        let mut lines = vec![
+            format!("pc\t{}", fmt_hex(self.pc as u32)),
+            format!("cycles\t{}", self.cycle),
+            format!("flags\t{}", fmt_ordering(self.flags)),
+            format!("stack\t{:?}", self.stack),
+            format!("call stack\t{:?}", self.call_stack),
+        ];
+        lines.extend(
+            (1..32)
+                .filter(|&reg| !matches!(reg, REG_PC | REG_IO | REG_KB | REG_RNG))
+                .filter(|&reg| self.regs[reg as usize] != 0)
+                .map(|reg| format!("{}\t{}", fmt_reg(reg), fmt_hex(self.regs[reg as usize]))),
+        );
+
+        align_tabbed_lines(&lines).map(|line| line + "\n").collect()
+    }
+
+    fn check_cond(&self, cond: u32) -> Result<bool> {
+        Ok(match cond {
+            0b000 => true,
+            0b001 => self.flags == Ordering::Equal,
+            0b010 => self.flags != Ordering::Equal,
+            0b011 => self.flags == Ordering::Less,
+            0b100 => self.flags != Ordering::Less,
+            0b101 => self.flags == Ordering::Greater,
+            0b110 => self.flags != Ordering::Greater,
+            _ => bail!(
+                "Invalid condition at {}: {:03b}",
+                fmt_hex(self.pc as u32),
+                cond
+            ),
+        })
+    }
+
+    fn execute(&mut self, name: &str, ops: &[u32], effects: &mut Vec<Effect>) -> Result<()> {
+        let alu = |name: &str, a: u32, b: u32| -> u32 {
+            match name {
+                "add" => a.wrapping_add(b),
+                "sub" => a.wrapping_sub(b),
+                "mulh" => ((a as u64 * b as u64) >> 32) as u32,
+                "mull" => a.wrapping_mul(b),
+                "mod" => a.checked_rem(b).unwrap_or(0),
+                "div" => a.checked_div(b).unwrap_or(0),
+                "and" => a & b,
+                "nand" => !(a & b),
+                "or" => a | b,
+                "nor" => !(a | b),
+                "xor" => a ^ b,
+                "xnor" => !(a ^ b),
+                "shl" => a.wrapping_shl(b),
+                "shr" => a.wrapping_shr(b),
+                "rol" => a.rotate_left(b),
+                "ror" => a.rotate_right(b),
+                "ashr" => (a as i32).wrapping_shr(b) as u32,
+                _ => unreachable!(),
+            }
+        };
+
+        match name {
+            "add" | "sub" | "mulh" | "mull" | "mod" | "div" | "and" | "nand" | "or" | "nor"
+            | "xor" | "xnor" | "shl" | "shr" | "rol" | "ror" | "ashr" => {
+                let a = self.read_reg(ops[1], effects);
+                let b = self.read_reg(ops[2], effects);
+                self.write_reg(ops[0], alu(name, a, b), effects);
+            }
+            "addi" | "subi" | "mulhi" | "mulli" | "modi" | "divi" | "andi" | "nandi" | "ori"
+            | "nori" | "xori" | "xnori" | "shli" | "shri" | "roli" | "rori" | "ashri" => {
+                let a = self.read_reg(ops[1], effects);
+                self.write_reg(ops[0], alu(&name[..name.len() - 1], a, ops[2]), effects);
+            }
+            "not" => {
+                let a = self.read_reg(ops[1], effects);
+                self.write_reg(ops[0], !a, effects);
+            }
+            "cmp" | "cmpi" => {
+                let a = self.read_reg(ops[0], effects);
+                let b = if name == "cmp" {
+                    self.read_reg(ops[1], effects)
+                } else {
+                    ops[1]
+                };
+                self.flags = a.cmp(&b);
+                effects.push(Effect::Flags(self.flags));
+            }
+            "lw" => {
+                let addr = self.read_reg(ops[1], effects).wrapping_add(ops[2]);
+                let addr = self.machine.map_addr(addr)?;
+                let value = self.mem(addr);
+                effects.push(Effect::MemRead { addr, value });
+                self.write_reg(ops[0], value, effects);
+            }
+            "sw" => {
+                let addr = self.read_reg(ops[0], effects).wrapping_add(ops[2]);
+                let addr = self.machine.map_addr(addr)?;
+                let value = self.read_reg(ops[1], effects);
+                self.set_mem(addr, value);
+                effects.push(Effect::MemWrite { addr, value });
+            }
+            "li" => self.write_reg(ops[0], ops[1], effects),
+            "lui" => self.write_reg(ops[0], ops[1] << 12, effects),
+            "jmp" => self.jump(ops[0] as usize, effects),
+            "beq" | "bne" | "blt" | "ble" | "bgt" | "bge" => {
+                let a = self.read_reg(ops[0], effects);
+                let b = self.read_reg(ops[1], effects);
+                let taken = match name {
+                    "beq" => a == b,
+                    "bne" => a != b,
+                    "blt" => a < b,
+                    "ble" => a <= b,
+                    "bgt" => a > b,
+                    "bge" => a >= b,
+                    _ => unreachable!(),
+                };
+                if taken {
+                    self.jump(ops[2] as usize, effects);
+                }
+            }
+            "peek" => {
+                let value = *self
+                    .stack
+                    .last()
+                    .ok_or_else(|| anyhow!("Stack underflow"))?;
+                effects.push(Effect::Peek(value));
+                self.write_reg(ops[0], value, effects);
+            }
+            "pop" => {
+                let value = self.stack.pop().ok_or_else(|| anyhow!("Stack underflow"))?;
+                effects.push(Effect::Pop(value));
+                self.write_reg(ops[0], value, effects);
+            }
+            "push" | "pushi" => {
+                let value = if name == "push" {
+                    self.read_reg(ops[0], effects)
+                } else {
+                    ops[0]
+                };
+                if self.stack.len() >= self.machine.stack_depth {
+                    bail!("Stack overflow (depth {})", self.machine.stack_depth);
+                }
+                self.stack.push(value);
+                effects.push(Effect::Push(value));
+            }
+            "call" => {
+                if self.call_stack.len() >= self.machine.call_depth {
+                    bail!("Call stack overflow (depth {})", self.machine.call_depth);
+                }
+                let target = ops[0] as usize;
+                effects.push(Effect::Call {
+                    target,
+                    ret: self.pc,
+                });
+                self.call_stack.push(self.pc);
+                self.pc = target;
+            }
+            "ret" => {
+                let to = self
+                    .call_stack
+                    .pop()
+                    .ok_or_else(|| anyhow!("Return with an empty call stack"))?;
+                self.pc = to;
+                effects.push(Effect::Ret(to));
+            }
+            "col" => {
+                self.color = ops[0];
+                effects.push(Effect::Color(self.color));
+            }
+            "spx" => {
+                let x = self.read_reg(ops[0], effects);
+                let y = self.read_reg(ops[1], effects);
+                effects.push(Effect::Pixel {
+                    x,
+                    y,
+                    color: self.color,
+                });
+            }
+            "seg" => {
+                let value = self.read_reg(ops[0], effects);
+                effects.push(Effect::Segment(value));
+            }
+            "segi" => effects.push(Effect::Segment(ops[0])),
+            _ => unreachable!("Internal Error: instruction '{}' is not emulated", name),
+        }
+
+        Ok(())
+    }
+
+    fn jump(&mut self, target: usize, effects: &mut Vec<Effect>) {
+        self.pc = target;
+        effects.push(Effect::Jump(target));
+    }
+
+    fn read_reg(&mut self, reg: u32, effects: &mut Vec<Effect>) -> u32 {
+        let value = match reg {
+            REG_ZERO => return 0,
+            REG_PC => return (self.pc - 1) as u32,
+            REG_IO => self.inputs.pop_front().unwrap_or(0),
+            REG_KB => self.keys.pop_front().unwrap_or(0),
+            REG_RNG => self.next_random(),
+            _ => return self.regs[reg as usize],
+        };
+
+        effects.push(Effect::RegRead { reg, value });
+        value
+    }
+
+    fn write_reg(&mut self, reg: u32, value: u32, effects: &mut Vec<Effect>) {
+        match reg {
+            REG_ZERO | REG_PC | REG_KB | REG_RNG => {}
+            REG_IO => {
+                self.outputs.push(value);
+                effects.push(Effect::Output(value));
+            }
+            _ => {
+                self.regs[reg as usize] = value;
+                effects.push(Effect::RegWrite { reg, value });
+            }
+        }
+    }
+
+    // xorshift32
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Effect::RegRead { reg, value } => write!(f, "{}->{}", fmt_reg(*reg), fmt_hex(*value)),
+            Effect::RegWrite { reg, value } => write!(f, "{}={}", fmt_reg(*reg), fmt_hex(*value)),
+            Effect::Flags(ord) => write!(f, "flags={}", fmt_ordering(*ord)),
+            Effect::MemRead { addr, value } => {
+                write!(f, "[{}]->{}", fmt_hex(*addr), fmt_hex(*value))
+            }
+            Effect::MemWrite { addr, value } => {
+                write!(f, "[{}]={}", fmt_hex(*addr), fmt_hex(*value))
+            }
+            Effect::Push(value) => write!(f, "push {}", fmt_hex(*value)),
+            Effect::Pop(value) => write!(f, "pop {}", fmt_hex(*value)),
+            Effect::Peek(value) => write!(f, "peek {}", fmt_hex(*value)),
+            Effect::Jump(target) => write!(f, "jump {}", fmt_hex(*target as u32)),
+            Effect::Call { target, ret } => write!(
+                f,
+                "call {} ret {}",
+                fmt_hex(*target as u32),
+                fmt_hex(*ret as u32)
+            ),
+            Effect::Ret(to) => write!(f, "ret {}", fmt_hex(*to as u32)),
+            Effect::Color(color) => write!(f, "col 0x{:06X}", color),
+            Effect::Pixel { x, y, color } => write!(f, "spx ({}, {}) 0x{:06X}", x, y, color),
+            Effect::Segment(value) => write!(f, "seg {}", fmt_hex(*value)),
+            Effect::Output(value) => write!(f, "io<-{}", fmt_hex(*value)),
+        }
+    }
+}
+
+pub fn fmt_ordering(ord: Ordering) -> &'static str {
+    match ord {
+        Ordering::Less => "lt",
+        Ordering::Equal => "eq",
+        Ordering::Greater => "gt",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{machine::AddressMode, *};
+    use crate::testkit::*;
+
+    #[test]
+    fn fib() {
+        let program = assemble(include_str!("../examples/fib.asm"));
+        let (emu, result) = run(&program, 100_000);
+
+        assert_snapshot!(format!("{:?}", emu.outputs), @"[55]");
+        assert_snapshot!(result, @"Halted: jump to itself");
+    }
+
+    #[test]
+    fn predicate_and_effects() {
+        let program = assemble(
+            "
+            li r1 3
+            cmp r1 5
+            li.gt r2 1
+            li.lt r2 2
+            push r2
+            sw r1 r2 4
+            lw r3 r1 4
+            pop r4
+            jmp 8
+            ",
+        );
+        assert_snapshot!(steps(&program, 9), @r"
+        + li r1 3     r1=3
+        + cmpi r1 5   flags=lt
+        - li.gt r2 1
+        + li.lt r2 2  r2=2
+        + push r2     push 2
+        + sw r1 r2 4  [7]=2
+        + lw r3 r1 4  [7]->2 r3=2
+        + pop r4      pop 2 r4=2
+        + jmp 8       jump 8
+        ");
+    }
+
+    #[test]
+    fn machine_profile() {
+        let machine = |stack_depth, call_depth, ram_size, address_mode| MachineProfile {
+            stack_depth,
+            call_depth,
+            ram_size,
+            address_mode,
+        };
+
+        let program = assemble("loop: pushi 1\njmp loop");
+        let (emu, result) = run_with(&program, machine(4, 4, 16, AddressMode::Fault), 100);
+        assert_snapshot!(result, @"Error: Stack overflow (depth 4)");
+        assert_snapshot!(emu.stack_depth(), @"4");
+
+        let program = assemble("pop r1");
+        let (_, result) = run(&program, 100);
+        assert_snapshot!(result, @"Error: Stack underflow");
+
+        let program = assemble("f: call f");
+        let (_, result) = run_with(&program, machine(4, 8, 16, AddressMode::Fault), 100);
+        assert_snapshot!(result, @"Error: Call stack overflow (depth 8)");
+
+        let program = assemble("ret");
+        let (_, result) = run(&program, 100);
+        assert_snapshot!(result, @"Error: Return with an empty call stack");
+
+        let program = assemble("li r1 20\nsw r1 r1 0\nlw r2 zero 4\nhalt: jmp halt");
+        let (_, result) = run_with(&program, machine(4, 4, 16, AddressMode::Fault), 100);
+        assert_snapshot!(result, @"Error: Memory access out of bounds: 20 (RAM size 16)");
+
+        let (emu, result) = run_with(&program, machine(4, 4, 16, AddressMode::Wrap), 100);
+        assert_snapshot!(result, @"Halted: jump to itself");
+        assert_snapshot!(emu.reg(2), @"20");
+    }
+}
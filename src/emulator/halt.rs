@@ -0,0 +1,83 @@
+use std::{
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// Why the emulator considers the program halted.
+///
+/// ArchP has no halt instruction, programs end in a `jmp` to itself or spin
+/// on a device register that has nothing more to give.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Halt {
+    /// An executed jump to its own address.
+    SelfJump,
+    /// The machine came back to a state it was in before, with no pending input.
+    RepeatedState,
+}
+
+impl Display for Halt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Halt::SelfJump => write!(f, "jump to itself"),
+            Halt::RepeatedState => write!(f, "repeated machine state with no pending input"),
+        }
+    }
+}
+
+/// Brent's cycle detection over hashes of the machine state, sampled at backward jumps.
+pub struct LoopDetector {
+    saved: Option<u64>,
+    power: u64,
+    length: u64,
+}
+
+impl LoopDetector {
+    pub fn new() -> Self {
+        LoopDetector {
+            saved: None,
+            power: 1,
+            length: 0,
+        }
+    }
+
+    /// Returns `true` if the state was seen before.
+    pub fn check(&mut self, state: u64) -> bool {
+        if self.saved == Some(state) {
+            return true;
+        }
+
+        self.length += 1;
+        if self.saved.is_none() || self.length == self.power {
+            self.saved = Some(state);
+            self.power *= 2;
+            self.length = 0;
+        }
+
+        false
+    }
+}
+
+/// The contribution of a memory word to the memory hash, zero words contribute nothing.
+pub fn mem_entry_hash(addr: u32, value: u32) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    (addr, value).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_detector() {
+        let mut detector = LoopDetector::new();
+        let states = [1, 2, 3, 4, 5, 3, 4, 5, 3, 4, 5, 3, 4, 5];
+
+        let found = states.iter().position(|&s| detector.check(s));
+        assert_eq!(found, Some(5));
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+
+use crate::utils::fmt_hex;
+
+/// The sizes of the hardware behind the ISA, which are finite in the game.
+#[derive(Debug, Clone, Args)]
+pub struct MachineProfile {
+    /// Depth of the stack used by `push`/`pop`.
+    #[arg(long, default_value_t = 256)]
+    pub stack_depth: usize,
+
+    /// Depth of the call stack used by `call`/`ret`.
+    #[arg(long, default_value_t = 256)]
+    pub call_depth: usize,
+
+    /// Number of words of RAM behind `lw`/`sw`.
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ram_size: u32,
+
+    /// What happens on an access beyond the RAM size.
+    #[arg(long, value_enum, default_value_t = AddressMode::Fault)]
+    pub address_mode: AddressMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AddressMode {
+    /// Wrap the address around the RAM size.
+    Wrap,
+    /// Stop with an error.
+    Fault,
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        MachineProfile {
+            stack_depth: 256,
+            call_depth: 256,
+            ram_size: 256,
+            address_mode: AddressMode::Fault,
+        }
+    }
+}
+
+impl MachineProfile {
+    /// Maps `addr` into the RAM according to the address mode.
+    pub fn map_addr(&self, addr: u32) -> Result<u32> {
+        if addr < self.ram_size {
+            return Ok(addr);
+        }
+
+        match self.address_mode {
+            AddressMode::Wrap => Ok(addr % self.ram_size),
+            AddressMode::Fault => bail!(
+                "Memory access out of bounds: {} (RAM size {})",
+                fmt_hex(addr),
+                fmt_hex(self.ram_size)
+            ),
+        }
+    }
+}
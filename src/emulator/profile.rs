@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    assembler::Program,
+    emulator::{Effect, Step},
+    utils::align_tabbed_lines,
+};
+
+/// Counts executed words per address and per function.
+///
+/// Functions are the targets of executed `call`s, plus the entry at address 0.
+/// Every word costs one cycle, including the ones skipped by their predicate.
+pub struct Profiler<'a> {
+    program: &'a Program,
+    cycles: u64,
+    /// `(executed, skipped)` for each address.
+    hits: Vec<(u64, u64)>,
+    functions: BTreeMap<usize, FuncStats>,
+    frames: Vec<Frame>,
+    /// The number of active frames of each function, to not count recursion twice.
+    active: HashMap<usize, usize>,
+}
+
+#[derive(Default)]
+struct FuncStats {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+struct Frame {
+    func: usize,
+    start: u64,
+}
+
+impl<'a> Profiler<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let mut profiler = Profiler {
+            program,
+            cycles: 0,
+            hits: vec![(0, 0); program.codes.len()],
+            functions: BTreeMap::new(),
+            frames: Vec::new(),
+            active: HashMap::new(),
+        };
+        profiler.enter(0, 0);
+        profiler
+    }
+
+    pub fn record(&mut self, step: &Step) {
+        self.cycles += 1;
+
+        let (executed, skipped) = &mut self.hits[step.pc];
+        if step.executed {
+            *executed += 1;
+        } else {
+            *skipped += 1;
+        }
+
+        if let Some(frame) = self.frames.last() {
+            self.functions.entry(frame.func).or_default().exclusive += 1;
+        }
+
+        for effect in &step.effects {
+            match *effect {
+                Effect::Call { target, .. } => self.enter(target, self.cycles),
+                Effect::Ret(_) if self.frames.len() > 1 => self.leave(self.cycles),
+                _ => {}
+            }
+        }
+    }
+
+    fn enter(&mut self, func: usize, start: u64) {
+        self.functions.entry(func).or_default().calls += 1;
+        *self.active.entry(func).or_default() += 1;
+        self.frames.push(Frame { func, start });
+    }
+
+    fn leave(&mut self, end: u64) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+
+        let active = self.active.entry(frame.func).or_default();
+        *active -= 1;
+        if *active == 0 {
+            self.functions.entry(frame.func).or_default().inclusive += end - frame.start;
+        }
+    }
+
+    /// Closes the frames still active at the end of the run.
+    pub fn finish(&mut self) {
+        while !self.frames.is_empty() {
+            self.leave(self.cycles);
+        }
+    }
+
+    /// A table of functions and a table of labels, both sorted by the cycles spent in them.
+    pub fn report(&self) -> String {
+        let mut functions = self
+            .functions
+            .iter()
+            .map(|(&addr, stats)| (self.program.symbol(addr), stats))
+            .collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(&b.0)));
+
+        let mut rows = vec!["function\tcalls\tinclusive\texclusive\t%".to_string()];
+        rows.extend(functions.into_iter().map(|(name, stats)| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                name,
+                stats.calls,
+                stats.inclusive,
+                stats.exclusive,
+                self.percent(stats.exclusive)
+            )
+        }));
+
+        let mut report = align_tabbed_lines(&rows).collect::<Vec<_>>().join("\n");
+
+        let mut labels = self
+            .regions()
+            .into_iter()
+            .map(|(name, start, end)| {
+                let (executed, skipped) = self.hits[start..end]
+                    .iter()
+                    .fold((0, 0), |acc, hit| (acc.0 + hit.0, acc.1 + hit.1));
+                (name, executed, skipped)
+            })
+            .filter(|&(_, executed, skipped)| executed + skipped > 0)
+            .collect::<Vec<_>>();
+        labels.sort_by(|a, b| (b.1 + b.2).cmp(&(a.1 + a.2)).then(a.0.cmp(&b.0)));
+
+        let mut rows = vec!["label\texecuted\tskipped\t%".to_string()];
+        rows.extend(labels.into_iter().map(|(name, executed, skipped)| {
+            format!(
+                "{}\t{}\t{}\t{}",
+                name,
+                executed,
+                skipped,
+                self.percent(executed + skipped)
+            )
+        }));
+
+        report += "\n\n";
+        report += &align_tabbed_lines(&rows).collect::<Vec<_>>().join("\n");
+        report += &format!("\n\ntotal cycles: {}\n", self.cycles);
+
+        report
+    }
+
+    /// The assembled listing, prefixed with executed and skipped counts of each word.
+    pub fn annotate(&self) -> String {
+        let rows = self
+            .program
+            .codes
+            .iter()
+            .zip(&self.program.displays)
+            .zip(&self.hits)
+            .map(|((code, display), (executed, skipped))| {
+                format!("{}\t{}\t0x{:08X} # {}", executed, skipped, code, display)
+            })
+            .collect::<Vec<_>>();
+
+        align_tabbed_lines(&rows).map(|line| line + "\n").collect()
+    }
+
+    /// Splits the program into `(label, start, end)` regions.
+    fn regions(&self) -> Vec<(String, usize, usize)> {
+        let mut starts = self
+            .program
+            .labels
+            .right_values()
+            .copied()
+            .collect::<Vec<_>>();
+        starts.push(0);
+        starts.sort();
+        starts.dedup();
+
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(self.hits.len());
+                (self.program.symbol(start), start, end)
+            })
+            .collect()
+    }
+
+    fn percent(&self, cycles: u64) -> String {
+        if self.cycles == 0 {
+            "0.0".to_string()
+        } else {
+            format!("{:.1}", cycles as f64 * 100.0 / self.cycles as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn profile() {
+        let program = assemble(
+            "
+            main:
+                li r1 3
+                loop:
+                    call work
+                    dec r1
+                    cmp r1 0
+                    jmp.ne loop
+            halt:
+                jmp halt
+            work:
+                cmp r1 2
+                li.eq r2 1
+                ret
+            ",
+        );
+        let mut emu = emulator(&program);
+        let mut profiler = Profiler::new(&program);
+
+        for _ in 0..25 {
+            profiler.record(&emu.step().unwrap());
+        }
+        profiler.finish();
+
+        assert_snapshot!(profiler.report(), @r"
+        function  calls  inclusive  exclusive  %
+        main      1      25         16         64.0
+        work      3      9          9          36.0
+
+        label  executed  skipped  %
+        loop   11        1        48.0
+        work   7         2        36.0
+        halt   3         0        12.0
+        main   1         0        4.0
+
+        total cycles: 25
+        ");
+        assert_snapshot!(profiler.annotate(), @r"
+        1  0  0x84020003 # li r1 3                      <label: main>
+        3  0  0xAA000006 # call 6        [call work]    <label: loop>
+        3  0  0x42021001 # subi r1 r1 1  [dec r1]
+        3  0  0x70001000 # cmpi r1 0     [cmp r1 0]
+        2  1  0x90800020 # jmp.ne 1      [jmp.ne loop]
+        3  0  0x900000A0 # jmp 5         [jmp halt]     <label: halt>
+        3  0  0x70001002 # cmpi r1 2     [cmp r1 2]     <label: work>
+        1  2  0x84440001 # li.eq r2 1
+        3  0  0xA8000000 # ret
+        ");
+    }
+}
@@ -0,0 +1,172 @@
+use std::{io::Write, ops::Range};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+use crate::{
+    assembler::Program,
+    emulator::{Effect, Step, fmt_ordering},
+    instructions::{disassemble, fmt_reg},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum TraceFormat {
+    /// One human-readable line per executed word.
+    Text,
+    /// One JSON object per executed word.
+    Jsonl,
+}
+
+/// Writes executed words to `out`, optionally restricted to address ranges.
+pub struct Tracer<'a, W: Write> {
+    program: &'a Program,
+    out: W,
+    format: TraceFormat,
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a, W: Write> Tracer<'a, W> {
+    pub fn new(
+        program: &'a Program,
+        out: W,
+        format: TraceFormat,
+        ranges: Vec<Range<usize>>,
+    ) -> Self {
+        Tracer {
+            program,
+            out,
+            format,
+            ranges,
+        }
+    }
+
+    pub fn record(&mut self, step: &Step) -> Result<()> {
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|r| r.contains(&step.pc)) {
+            return Ok(());
+        }
+
+        let asm = disassemble(step.code).unwrap_or_default();
+        let (line_idx, source) = &self.program.source_map[step.pc];
+
+        match self.format {
+            TraceFormat::Text => {
+                let effects = step
+                    .effects
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                writeln!(
+                    self.out,
+                    "{:>8}  0x{:03X}  {} {:<24}  {:<32}  # {}: {}",
+                    step.cycle,
+                    step.pc,
+                    if step.executed { '+' } else { '-' },
+                    asm,
+                    effects,
+                    line_idx + 1,
+                    source
+                )?;
+            }
+            TraceFormat::Jsonl => {
+                let record = json!({
+                    "cycle": step.cycle,
+                    "pc": step.pc,
+                    "code": format!("0x{:08X}", step.code),
+                    "asm": asm,
+                    "executed": step.executed,
+                    "effects": step.effects.iter().map(effect_to_json).collect::<Vec<_>>(),
+                    "line": line_idx + 1,
+                    "source": source,
+                });
+                writeln!(self.out, "{}", record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+fn effect_to_json(effect: &Effect) -> Value {
+    match effect {
+        Effect::RegRead { reg, value } => {
+            json!({ "kind": "reg_read", "reg": fmt_reg(*reg), "value": value })
+        }
+        Effect::RegWrite { reg, value } => {
+            json!({ "kind": "reg_write", "reg": fmt_reg(*reg), "value": value })
+        }
+        Effect::Flags(ord) => json!({ "kind": "flags", "value": fmt_ordering(*ord) }),
+        Effect::MemRead { addr, value } => {
+            json!({ "kind": "mem_read", "addr": addr, "value": value })
+        }
+        Effect::MemWrite { addr, value } => {
+            json!({ "kind": "mem_write", "addr": addr, "value": value })
+        }
+        Effect::Push(value) => json!({ "kind": "push", "value": value }),
+        Effect::Pop(value) => json!({ "kind": "pop", "value": value }),
+        Effect::Peek(value) => json!({ "kind": "peek", "value": value }),
+        Effect::Jump(target) => json!({ "kind": "jump", "target": target }),
+        Effect::Call { target, ret } => json!({ "kind": "call", "target": target, "ret": ret }),
+        Effect::Ret(to) => json!({ "kind": "ret", "target": to }),
+        Effect::Color(color) => json!({ "kind": "color", "value": color }),
+        Effect::Pixel { x, y, color } => {
+            json!({ "kind": "pixel", "x": x, "y": y, "color": color })
+        }
+        Effect::Segment(value) => json!({ "kind": "segment", "value": value }),
+        Effect::Output(value) => json!({ "kind": "output", "value": value }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    fn trace(format: TraceFormat, ranges: Vec<Range<usize>>) -> String {
+        let program = assemble(
+            "
+            li r1 2
+            loop:
+                dec r1
+                cmp r1 0
+                jmp.ne loop
+            ",
+        );
+        let mut emu = emulator(&program);
+        let mut tracer = Tracer::new(&program, Vec::new(), format, ranges);
+
+        for _ in 0..7 {
+            tracer.record(&emu.step().unwrap()).unwrap();
+        }
+
+        String::from_utf8(tracer.out).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        assert_snapshot!(trace(TraceFormat::Text, vec![]), @r"
+               0  0x000  + li r1 2                   r1=2                              # 2: li r1 2
+               1  0x001  + subi r1 r1 1              r1=1                              # 4: dec r1
+               2  0x002  + cmpi r1 0                 flags=gt                          # 5: cmp r1 0
+               3  0x003  + jmp.ne 1                  jump 1                            # 6: jmp.ne loop
+               4  0x001  + subi r1 r1 1              r1=0                              # 4: dec r1
+               5  0x002  + cmpi r1 0                 flags=eq                          # 5: cmp r1 0
+               6  0x003  - jmp.ne 1                                                    # 6: jmp.ne loop
+        ");
+    }
+
+    #[test]
+    fn jsonl() {
+        assert_snapshot!(trace(TraceFormat::Jsonl, vec![0..1, 3..4]), @r#"
+        {"asm":"li r1 2","code":"0x84020002","cycle":0,"effects":[{"kind":"reg_write","reg":"r1","value":2}],"executed":true,"line":2,"pc":0,"source":"li r1 2"}
+        {"asm":"jmp.ne 1","code":"0x90800020","cycle":3,"effects":[{"kind":"jump","target":1}],"executed":true,"line":6,"pc":3,"source":"jmp.ne loop"}
+        {"asm":"jmp.ne 1","code":"0x90800020","cycle":6,"effects":[],"executed":false,"line":6,"pc":3,"source":"jmp.ne loop"}
+        "#);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::{
+    instructions::{parse_color, parse_imm},
+    pass1::{split_comment, tokenize},
+};
+
+/// Spaces per level of indentation in the output.
+const INDENT: usize = 2;
+
+/// Blank lines kept in a row, more are dropped.
+const MAX_BLANK_LINES: usize = 2;
+
+enum Line<'a> {
+    Blank,
+    /// A line holding only a comment, kept at its level.
+    Comment(usize, String),
+    Const(usize, &'a str, String, Option<String>),
+    Label(usize, &'a str, Option<String>),
+    /// A mnemonic or directive with its operands.
+    Code(usize, &'a str, Vec<String>, Option<String>),
+}
+
+/// Directives opening a block whose body is indented one level deeper.
+const OPENERS: [&str; 9] = [
+    ".if", ".ifdef", ".ifndef", ".while", ".for", ".rept", ".irp", ".struct", ".enum",
+];
+
+/// Directives closing a block, at the level of their opener.
+const CLOSERS: [&str; 6] = [
+    ".endif",
+    ".endwhile",
+    ".endfor",
+    ".endr",
+    ".endstruct",
+    ".endenum",
+];
+
+/// The nesting of the source, giving the level of each line from its structure.
+#[derive(Default)]
+struct Nesting {
+    /// The level of the body of the current label: 1 for a global label, 2 for a local one.
+    body: usize,
+    /// The blocks open at this point.
+    blocks: usize,
+}
+
+impl Nesting {
+    /// The level of a line starting with `token`, then steps into what it opens.
+    fn level(&mut self, token: &str) -> usize {
+        if let Some(label) = token.strip_suffix(':') {
+            let local = label.starts_with('.') || label.starts_with(|c: char| c.is_ascii_digit());
+            let level = if local { self.body.min(1) } else { 0 };
+            self.body = level + 1;
+            return self.blocks + level;
+        }
+
+        match token {
+            ".func" | ".endfunc" => {
+                self.body = 0;
+                self.blocks
+            }
+            ".elif" | ".else" => self.body + self.blocks.saturating_sub(1),
+            _ if OPENERS.contains(&token) => {
+                self.blocks += 1;
+                self.body + self.blocks - 1
+            }
+            _ if CLOSERS.contains(&token) => {
+                self.blocks = self.blocks.saturating_sub(1);
+                self.body + self.blocks
+            }
+            _ => self.body + self.blocks,
+        }
+    }
+}
+
+/// Formats ArchP source, splitting lines with the same tokenizer as `Pass1`.
+///
+/// Labels get a line of their own. The level of a line comes from the structure of the
+/// source: the body of a global label is indented one level, local and anonymous labels one
+/// level and their body two, and the body of a block like `.if` or `.rept` one level deeper
+/// than the block. Comment lines take the level of the next line of code. Within runs of
+/// code lines the operands and the trailing comments are aligned, and so are the values of
+/// runs of `const` lines. Hexadecimal digits and colors are uppercased and leading zeros of
+/// decimals removed.
+pub fn format(source_lines: &[String]) -> String {
+    let mut nesting = Nesting::default();
+    let mut lines = Vec::new();
+    for line in source_lines {
+        let (code, comment) = split_comment(line);
+        let comment = comment.map(|(marker, text)| format!("{}{}", marker, text.trim_end()));
+        let mut tokens = tokenize(code);
+
+        if tokens.is_empty() {
+            lines.push(match comment {
+                Some(comment) => Line::Comment(0, comment),
+                None => Line::Blank,
+            });
+            continue;
+        }
+
+        // A `const` missing its value is left to the assembler to report.
+        if tokens[0] == "const" && tokens.len() >= 3 {
+            let value = tokens[2..].iter().map(|&t| number(t)).collect::<Vec<_>>();
+            let level = nesting.level("const");
+            lines.push(Line::Const(level, tokens[1], value.join(" "), comment));
+            continue;
+        }
+
+        if let Some(label) = tokens[0].strip_suffix(':') {
+            let level = nesting.level(tokens[0]);
+            if tokens.len() == 1 {
+                lines.push(Line::Label(level, label, comment));
+                continue;
+            }
+            lines.push(Line::Label(level, label, None));
+            tokens.remove(0);
+        }
+
+        let level = nesting.level(tokens[0]);
+        let ops = tokens[1..].iter().map(|&t| number(t)).collect();
+        lines.push(Line::Code(level, tokens[0], ops, comment));
+    }
+
+    let mut next = 0;
+    for line in lines.iter_mut().rev() {
+        match line {
+            Line::Blank => {}
+            Line::Comment(level, _) => *level = next,
+            Line::Const(level, ..) | Line::Label(level, ..) | Line::Code(level, ..) => {
+                next = *level
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut blanks = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let (group, rows) = group(&lines[i..]);
+        i += group;
+
+        match &lines[i - group] {
+            Line::Blank => {
+                if !out.is_empty() && blanks < MAX_BLANK_LINES {
+                    out.push(String::new());
+                }
+                blanks += 1;
+                continue;
+            }
+            Line::Comment(level, comment) => out.push(pad(*level) + comment),
+            Line::Label(level, label, comment) => out.push(with_comment(
+                format!("{}{}:", pad(*level), label),
+                comment.as_deref(),
+                0,
+            )),
+            Line::Const(..) | Line::Code(..) => out.extend(rows),
+        }
+        blanks = 0;
+    }
+
+    while out.last().is_some_and(|line| line.is_empty()) {
+        out.pop();
+    }
+
+    out.into_iter().map(|line| line + "\n").collect()
+}
+
+/// The length of the run of aligned lines starting `lines`, and the formatted run.
+fn group(lines: &[Line]) -> (usize, Vec<String>) {
+    match lines[0] {
+        Line::Const(level, ..) => {
+            let run = lines
+                .iter()
+                .map_while(|line| match line {
+                    Line::Const(l, name, value, comment) if *l == level => {
+                        Some((pad(level) + "const ", *name, value.clone(), comment))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            (run.len(), align(&run))
+        }
+        Line::Code(level, ..) => {
+            let run = lines
+                .iter()
+                .map_while(|line| match line {
+                    Line::Code(l, name, ops, comment) if *l == level => {
+                        Some((pad(level), *name, ops.join(" "), comment))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            (run.len(), align(&run))
+        }
+        _ => (1, Vec::new()),
+    }
+}
+
+/// Aligns rows of `(prefix, name, rest, comment)` on the start of `rest` and of the comments.
+fn align(rows: &[(String, &str, String, &Option<String>)]) -> Vec<String> {
+    let name_width = rows
+        .iter()
+        .filter(|(.., rest, _)| !rest.is_empty())
+        .map(|(_, name, ..)| width(name))
+        .max()
+        .unwrap_or(0);
+
+    let codes = rows
+        .iter()
+        .map(|(prefix, name, rest, _)| {
+            if rest.is_empty() {
+                format!("{}{}", prefix, name)
+            } else {
+                let padding = name_width - width(name) + 1;
+                format!("{}{}{}{}", prefix, name, " ".repeat(padding), rest)
+            }
+        })
+        .collect::<Vec<_>>();
+    let code_width = codes
+        .iter()
+        .zip(rows)
+        .filter(|(_, (.., comment))| comment.is_some())
+        .map(|(code, _)| width(code))
+        .max()
+        .unwrap_or(0);
+
+    codes
+        .into_iter()
+        .zip(rows)
+        .map(|(code, (.., comment))| with_comment(code, comment.as_deref(), code_width))
+        .collect()
+}
+
+fn with_comment(code: String, comment: Option<&str>, column: usize) -> String {
+    match comment {
+        Some(comment) => {
+            let padding = column.saturating_sub(width(&code)) + 1;
+            format!("{}{}{}", code, " ".repeat(padding), comment)
+        }
+        None => code,
+    }
+}
+
+/// Normalizes a number or color literal, other tokens are returned as they are.
+fn number(token: &str) -> String {
+    if token.starts_with('#') && parse_color(&token.into()).is_ok() {
+        return token.to_uppercase();
+    }
+    if !token.starts_with(|c: char| c.is_ascii_digit()) || parse_imm(&token.into()).is_err() {
+        return token.to_string();
+    }
+
+    if let Some(hex) = token.strip_prefix("0x") {
+        format!("0x{}", hex.to_uppercase())
+    } else if token.starts_with("0b") || token.starts_with("0o") {
+        token.to_string()
+    } else {
+        let mut trimmed = token;
+        while trimmed.starts_with('0') && trimmed[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            trimmed = &trimmed[1..];
+        }
+        trimmed.to_string()
+    }
+}
+
+fn pad(level: usize) -> String {
+    " ".repeat(level * INDENT)
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    fn f(src: &str) -> String {
+        format(&src.lines().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn layout() {
+        let src = "
+const a0 r4 # argument
+const SCREEN_WIDTH 0x0080
+const t r1
+
+
+#> test main
+main: li a0 007   ; load
+\t.loop:
+\t\tdec a0 # count down
+\t\tbne a0 zero .loop
+    cmp a0 0b10
+  call.eq work ; maybe
+.rept 2
+# twice
+nop
+        .ifdef DEBUG
+ inc a0
+      .else
+   dec a0
+.endif
+  .endr
+
+
+
+halt:
+  jmp halt
+work:
+\tconst n 010
+\tret
+";
+
+        assert_snapshot!(f(src), @r"
+        const a0           r4 # argument
+        const SCREEN_WIDTH 0x0080
+        const t            r1
+
+
+        #> test main
+        main:
+          li a0 7 ; load
+          .loop:
+            dec     a0   # count down
+            bne     a0 zero .loop
+            cmp     a0 0b10
+            call.eq work ; maybe
+            .rept   2
+              # twice
+              nop
+              .ifdef DEBUG
+                inc a0
+              .else
+                dec a0
+              .endif
+            .endr
+
+
+        halt:
+          jmp halt
+        work:
+          const n 10
+          ret
+        ");
+    }
+
+    #[test]
+    fn literals() {
+        assert_snapshot!(f("li a0   ' ' ; space\nli a1 0x00ff_ff00\nli a2 0010_000\nli a3 0o017"), @r"
+        li a0 ' ' ; space
+        li a1 0x00FF_FF00
+        li a2 10_000
+        li a3 0o017
+        ");
+        assert_snapshot!(f("col   #ff8000 # orange\ncol rgb(255,  128, 0)"), @r"
+        col #FF8000 # orange
+        col rgb(255,  128, 0)
+        ");
+    }
+
+    #[test]
+    fn malformed() {
+        assert_snapshot!(f("const\nconst   A\nconst B  1"), @r"
+        const
+        const A
+        const B 1
+        ");
+    }
+
+    #[test]
+    fn examples() {
+        for src in [
+            include_str!("../examples/fib.asm"),
+            include_str!("../examples/minesweeper.asm"),
+            include_str!("../examples/snake.asm"),
+            include_str!("../examples/trapping-rain-water.asm"),
+        ] {
+            let formatted = f(src);
+            assert_eq!(f(&formatted), formatted);
+            assert_eq!(assemble(&formatted).codes, assemble(src).codes);
+        }
+    }
+}
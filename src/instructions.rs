@@ -13,7 +13,10 @@ use std::{collections::HashMap, fmt::Display, num::IntErrorKind};
 use anyhow::{Result, anyhow, bail};
 use once_cell::sync::Lazy;
 
-use crate::operand::{ImmRange, OperandType, OperandValue, op_types};
+use crate::{
+    operand::{ImmRange, OperandType, OperandValue, op_types},
+    utils::fmt_line,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum InstrType {
@@ -49,7 +52,28 @@ pub static INSTRUCTIONS: Lazy<HashMap<&'static str, Instruction>> = Lazy::new(||
     map
 });
 
+pub static OPCODES: Lazy<HashMap<u32, Instruction>> = Lazy::new(|| {
+    let mut map = HashMap::new();
+    for entry in inventory::iter::<Instruction> {
+        map.insert(entry.opcode, *entry);
+    }
+    map
+});
+
 impl Instruction {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn opcode(&self) -> u32 {
+        self.opcode
+    }
+
+    /// The encoding format of the instruction, e.g. `R` or `I`.
+    pub fn itype(&self) -> impl Display {
+        self.itype
+    }
+
     pub fn encode(&self, cond: Option<&str>, operands: &[OperandValue]) -> Result<u32> {
         let cond = cond.map(parse_cond).transpose()?.unwrap_or(0);
 
@@ -72,6 +96,42 @@ impl Instruction {
         }
     }
 
+    /// The inverse of [`Instruction::encode`], returns the condition and the operands
+    /// in the same order as they are written in assembly.
+    pub fn decode(&self, code: u32) -> (u32, Vec<u32>) {
+        let cond = match self.itype {
+            InstrType::U | InstrType::C => 0,
+            _ => (code >> 22) & 0b111,
+        };
+
+        let fields = match self.itype {
+            InstrType::R => vec![(code >> 17) & 0x1F, (code >> 12) & 0x1F, code & 0x1F],
+            InstrType::I => vec![(code >> 17) & 0x1F, (code >> 12) & 0x1F, code & 0xFFF],
+            InstrType::B => vec![
+                (code >> 12) & 0x1F,
+                code & 0x1F,
+                (((code >> 17) & 0x1F) << 7) | ((code >> 5) & 0x7F),
+            ],
+            InstrType::U => vec![
+                (code >> 17) & 0x1F,
+                (((code >> 22) & 0b111) << 17) | (code & 0x1FFFF),
+            ],
+            InstrType::C => vec![code & 0xFFFFFF],
+        };
+
+        let operands = match self.encode_format {
+            Some(format) => fields
+                .into_iter()
+                .zip(format)
+                .filter(|(_, placeholder)| *placeholder == FormatPlaceholder::Some)
+                .map(|(field, _)| field)
+                .collect(),
+            None => fields,
+        };
+
+        (cond, operands)
+    }
+
     fn parse(&self, operands: &[OperandValue]) -> Result<Vec<u32>> {
         let mut parsed_operands = Vec::new();
         let operand_types = self.get_operand_types();
@@ -91,10 +151,17 @@ impl Instruction {
                 OperandType::Imm(range) => {
                     let imm = parse_imm(op)?;
 
-                    self.assert_immediate_range(imm, range)?;
+                    self.assert_immediate_range(op, imm, range)?;
 
                     parsed_operands.push(imm);
                 }
+                OperandType::Color => {
+                    let color = parse_color(op)?;
+
+                    self.assert_immediate_range(op, color, &ImmRange(0, 24))?;
+
+                    parsed_operands.push(color);
+                }
             }
         }
 
@@ -194,7 +261,20 @@ impl Instruction {
         Ok(())
     }
 
-    fn assert_immediate_range(&self, imm: u32, range: &ImmRange) -> Result<()> {
+    fn assert_immediate_range(&self, op: &OperandValue, imm: u32, range: &ImmRange) -> Result<()> {
+        let negative = match op {
+            OperandValue::StringSlice(s) => s.starts_with('-'),
+            OperandValue::Unsigned(n) => (*n as i32) < 0,
+        };
+        if negative && !range.contains(&imm) {
+            bail!(
+                "Negative immediate '{}' cannot be encoded in the unsigned field of {}-type instruction '{}', expected {}",
+                imm as i32,
+                self.itype,
+                self.name,
+                range
+            );
+        }
         if !range.contains(&imm) {
             bail!(
                 "Immediate value '{}' out of range for {}-type instruction '{}', expected {}",
@@ -208,7 +288,7 @@ impl Instruction {
         Ok(())
     }
 
-    fn get_operand_types(&self) -> &'static [OperandType] {
+    pub fn get_operand_types(&self) -> &'static [OperandType] {
         if let Some(ops) = self.operand_types {
             ops
         } else {
@@ -217,7 +297,7 @@ impl Instruction {
                 InstrType::I => op_types![RegD, RegS, Imm(12)],
                 InstrType::B => op_types![RegS, RegS, Imm(12)],
                 InstrType::U => op_types![RegD, Imm(20)],
-                InstrType::C => op_types![Imm(24)],
+                InstrType::C => op_types![Color],
             }
         }
     }
@@ -321,6 +401,65 @@ fn parse_cond(cond: &str) -> Result<u32> {
     }
 }
 
+const CONDS: [Option<&str>; 8] = [
+    None,
+    Some("eq"),
+    Some("ne"),
+    Some("lt"),
+    Some("ge"),
+    Some("gt"),
+    Some("le"),
+    None,
+];
+
+pub fn fmt_cond(cond: u32) -> Option<&'static str> {
+    CONDS[(cond & 0b111) as usize]
+}
+
+const REGS: [&str; 32] = [
+    "zero", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "r16", "r17", "r18", "r19", "r20", "r21", "r22", "r23", "r24", "pc", "io", "kb",
+    "rng", "r29", "r30", "tmp",
+];
+
+pub fn fmt_reg(reg: u32) -> &'static str {
+    REGS[(reg & 0x1F) as usize]
+}
+
+/// Disassemble a machine code word, returns `None` if the opcode is unknown.
+pub fn disassemble(code: u32) -> Option<String> {
+    let instr = OPCODES.get(&(code >> 25))?;
+    let (cond, operands) = instr.decode(code);
+
+    let ops = operands
+        .into_iter()
+        .zip(instr.get_operand_types())
+        .map(|(value, ty)| match ty {
+            OperandType::RegD | OperandType::RegS => OperandValue::StringSlice(fmt_reg(value)),
+            OperandType::Imm(_) | OperandType::Color => OperandValue::Unsigned(value),
+        })
+        .collect();
+
+    Some(fmt_line(instr.name, fmt_cond(cond), ops))
+}
+
+/// The registers read and written by a machine code word, returns `None` if the opcode is unknown.
+pub fn reg_usage(code: u32) -> Option<(Vec<u32>, Vec<u32>)> {
+    let instr = OPCODES.get(&(code >> 25))?;
+    let (_, operands) = instr.decode(code);
+
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+    for (value, ty) in operands.into_iter().zip(instr.get_operand_types()) {
+        match ty {
+            OperandType::RegD => writes.push(value),
+            OperandType::RegS => reads.push(value),
+            OperandType::Imm(_) | OperandType::Color => {}
+        }
+    }
+
+    Some((reads, writes))
+}
+
 macro err_expect_reg($e:expr) {
     bail!("Expected register, found immediate: {}", $e)
 }
@@ -399,19 +538,48 @@ pub fn parse_reg_s(op: &OperandValue) -> Result<u32> {
     }
 }
 
+/// Parses an immediate: a decimal, `0x` hexadecimal, `0o` octal or `0b` binary numeral,
+/// whose digits may be separated by underscores like `0x00FF_FF00`, or a character
+/// literal like `'a'` or `'\n'`. A leading `-` negates it in two's complement.
 pub fn parse_imm(imm: &OperandValue) -> Result<u32> {
-    let parse_str = |s: &str| match s {
-        s if let Some(hex) = s.strip_prefix("0x") => u32::from_str_radix(hex, 16),
-        s if let Some(bin) = s.strip_prefix("0b") => u32::from_str_radix(bin, 2),
-        s => s.parse(),
+    let s = match imm {
+        OperandValue::StringSlice(s) => *s,
+        OperandValue::Unsigned(n) => return Ok(*n),
     };
 
-    let parsed = match imm {
-        OperandValue::StringSlice(s) => parse_str(s),
-        OperandValue::Unsigned(n) => Ok(*n),
+    match s.strip_prefix('-') {
+        Some(magnitude) => match parse_magnitude(s, magnitude)? {
+            n if n <= 1 << 31 => Ok(n.wrapping_neg()),
+            _ => bail!("Immediate out of range of 32-bits: {}", s),
+        },
+        None => parse_magnitude(s, s),
+    }
+}
+
+/// Parses the unsigned part `s` of the immediate `imm`.
+fn parse_magnitude(imm: &str, s: &str) -> Result<u32> {
+    if s.starts_with('\'') {
+        return match parse_chars(s, '\'').as_deref() {
+            Some(&[c]) => Ok(c as u32),
+            _ => Err(anyhow!("Invalid character literal: {}", imm)),
+        };
+    }
+    if s.starts_with('"') {
+        bail!("Expected an immediate, found a string literal: {}", imm);
+    }
+
+    let (radix, digits) = match s {
+        s if let Some(hex) = s.strip_prefix("0x") => (16, hex),
+        s if let Some(oct) = s.strip_prefix("0o") => (8, oct),
+        s if let Some(bin) = s.strip_prefix("0b") => (2, bin),
+        s => (10, s),
     };
+    // Underscores only separate digits, so that names like `_1` are not numbers.
+    if !digits.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        bail!("Invalid immediate: {}", imm);
+    }
 
-    parsed.map_err(|err| {
+    u32::from_str_radix(&digits.replace('_', ""), radix).map_err(|err| {
         if err.kind() == &IntErrorKind::PosOverflow {
             anyhow!("Immediate out of range of 32-bits: {}", imm)
         } else {
@@ -420,6 +588,116 @@ pub fn parse_imm(imm: &OperandValue) -> Result<u32> {
     })
 }
 
+/// The named colors accepted by [`parse_color`].
+pub const PALETTE: &[(&str, u32)] = &[
+    ("black", 0x000000),
+    ("white", 0xFFFFFF),
+    ("gray", 0x808080),
+    ("red", 0xFF0000),
+    ("green", 0x00FF00),
+    ("blue", 0x0000FF),
+    ("yellow", 0xFFFF00),
+    ("cyan", 0x00FFFF),
+    ("magenta", 0xFF00FF),
+    ("orange", 0xFFA500),
+    ("purple", 0x800080),
+    ("brown", 0xA52A2A),
+    ("pink", 0xFFC0CB),
+];
+
+/// Parses the color of `col`: `#RRGGBB`, `rgb(R, G, B)`, a name of the [`PALETTE`] or
+/// an immediate.
+pub fn parse_color(op: &OperandValue) -> Result<u32> {
+    let s = match op {
+        OperandValue::StringSlice(s) => *s,
+        OperandValue::Unsigned(n) => return Ok(*n),
+    };
+
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid color: {} (expected '#RRGGBB')", s);
+        }
+        return Ok(u32::from_str_radix(hex, 16)?);
+    }
+
+    if let Some(args) = s.strip_prefix("rgb(").and_then(|s| s.strip_suffix(')')) {
+        let channels = args.split(',').map(str::trim).collect::<Vec<_>>();
+        let [r, g, b] = channels.as_slice() else {
+            bail!("Invalid color: {} (expected 'rgb(R, G, B)')", s);
+        };
+
+        let mut color = 0;
+        for (name, channel) in [("red", r), ("green", g), ("blue", b)] {
+            let value = parse_imm(&(*channel).into())?;
+            if value > 0xFF {
+                bail!(
+                    "Color channel out of range (0 ~ 255): {} ({} is {})",
+                    s,
+                    name,
+                    value as i32
+                );
+            }
+            color = color << 8 | value;
+        }
+        return Ok(color);
+    }
+
+    if let Some(&(_, color)) = PALETTE.iter().find(|&&(name, _)| name == s) {
+        return Ok(color);
+    }
+
+    if parse_reg_s(op).is_ok() {
+        bail!(
+            "Expected a color, found register: {} (the ISA has no register form of 'col')",
+            s
+        );
+    }
+
+    parse_imm(op).map_err(|e| {
+        if s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let names = PALETTE.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+            anyhow!(
+                "Unknown color: {} (expected one of {})",
+                s,
+                names.join(", ")
+            )
+        } else {
+            e
+        }
+    })
+}
+
+/// Parses a string literal like `"HI\n"` into its characters.
+pub fn parse_string(s: &str) -> Result<Vec<char>> {
+    parse_chars(s, '"').ok_or_else(|| anyhow!("Invalid string literal: {}", s))
+}
+
+/// The characters between the quotes of a literal, with the escapes `\n`, `\r`, `\t`, `\0`,
+/// `\\`, `\'`, `\"` and `\xHH`.
+fn parse_chars(s: &str, quote: char) -> Option<Vec<char>> {
+    let mut chars = s.strip_prefix(quote)?.strip_suffix(quote)?.chars();
+    let mut out = Vec::new();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'x' => {
+                    let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                    char::from(u8::from_str_radix(&hex, 16).ok()?)
+                }
+                c @ ('\\' | '\'' | '"') => c,
+                _ => return None,
+            },
+            c if c == quote => return None,
+            c => c,
+        });
+    }
+    Some(out)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{operand::OperandValue, testkit::*};
@@ -479,6 +757,26 @@ mod tests {
         assert_snapshot!(f("r1"), @"Error: Invalid immediate: r1");
         assert_snapshot!(f("invalid"), @"Error: Invalid immediate: invalid");
         assert_snapshot!(f("0x1FFFFFFFF"), @"Error: Immediate out of range of 32-bits: 0x1FFFFFFFF");
+        assert_snapshot!(f("0o52"), @"42");
+        assert_snapshot!(f("0x00FF_FF00"), @"16776960");
+        assert_snapshot!(f("1_000"), @"1000");
+        assert_snapshot!(f("_1"), @"Error: Invalid immediate: _1");
+        assert_snapshot!(f("0x_1"), @"Error: Invalid immediate: 0x_1");
+        assert_snapshot!(f("'a'"), @"97");
+        assert_snapshot!(f("' '"), @"32");
+        assert_snapshot!(f("'\\n'"), @"10");
+        assert_snapshot!(f("'\\x7F'"), @"127");
+        assert_snapshot!(f("'\\''"), @"39");
+        assert_snapshot!(f("'ab'"), @"Error: Invalid character literal: 'ab'");
+        assert_snapshot!(f("'\\q'"), @"Error: Invalid character literal: '\\q'");
+        assert_snapshot!(f("\"hi\""), @"Error: Expected an immediate, found a string literal: \"hi\"");
+        assert_snapshot!(f("-1"), @"4294967295");
+        assert_snapshot!(f("-0x10"), @"4294967280");
+        assert_snapshot!(f("-'a'"), @"4294967199");
+        assert_snapshot!(f("-2147483648"), @"2147483648");
+        assert_snapshot!(f("-2147483649"), @"Error: Immediate out of range of 32-bits: -2147483649");
+        assert_snapshot!(f("-r1"), @"Error: Invalid immediate: -r1");
+        assert_snapshot!(f("--1"), @"Error: Invalid immediate: --1");
     }
 
     #[test]
@@ -502,6 +800,7 @@ mod tests {
         assert_snapshot!(cmd("", &["r1", "r2", "r3"]), @"Error: Invalid immediate: r3");
         assert_snapshot!(cmd("", &["zero", "r2", "123"]), @"Error: Register 'zero' is raed-only");
         assert_snapshot!(cmd("", &["r1", "r2", "0xFFFF"]), @"Error: Immediate value '65535' out of range for I-type instruction 'addi', expected 0 ~ 0xFFF");
+        assert_snapshot!(cmd("", &["r1", "r2", "-1"]), @"Error: Negative immediate '-1' cannot be encoded in the unsigned field of I-type instruction 'addi', expected 0 ~ 0xFFF");
         assert_snapshot!(cmd("invalid", &["r1", "r2", "123"]), @"Error: Invalid condition: invalid");
         assert_snapshot!(cmd("ge", &["r4", "r5", "0b100"]), @"0100 000 100 00100 00101 0000000 00100");
 
@@ -529,14 +828,62 @@ mod tests {
         assert_snapshot!(cmd("", &["r3", "0xABCDE"]), @"1000 011 101 00011 01011 1100110 11110");
     }
 
+    #[test]
+    fn disassemble() {
+        let f = |code| super::disassemble(code).unwrap_or_else(|| "Error: Unknown opcode".into());
+        assert_snapshot!(f(0x8408000A), @"li r4 10");
+        assert_snapshot!(f(0x858A0001), @"li.le r5 1");
+        assert_snapshot!(f(0x40345000), @"addi io r5 0");
+        assert_snapshot!(f(0x70001002), @"cmpi r1 2");
+        assert_snapshot!(f(0xAA000003), @"call 3");
+        assert_snapshot!(f(0xA9800000), @"ret.le");
+        assert_snapshot!(f(0xA2060000), @"pop r3");
+        assert_snapshot!(f(0x000A2003), @"add r5 r2 r3");
+        assert_snapshot!(f(0xFE000000), @"Error: Unknown opcode");
+
+        let roundtrip = |cmd: &str, cond: &str, ops: &[&str]| {
+            let code = super::INSTRUCTIONS[cmd]
+                .encode(
+                    if cond.is_empty() { None } else { Some(cond) },
+                    &ops.iter()
+                        .map(|e| OperandValue::from(*e))
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap();
+            f(code)
+        };
+        assert_snapshot!(roundtrip("bne", "ne", &["r1", "zero", "3456"]), @"bne.ne r1 zero 0xD80");
+        assert_snapshot!(roundtrip("sw", "", &["r9", "r5", "7"]), @"sw r9 r5 7");
+        assert_snapshot!(roundtrip("lui", "", &["r3", "0xABCDE"]), @"lui r3 0xABCDE");
+        assert_snapshot!(roundtrip("col", "", &["0x123456"]), @"col 0x123456");
+        assert_snapshot!(roundtrip("jmp", "gt", &["4095"]), @"jmp.gt 0xFFF");
+    }
+
     #[test]
     fn encode_c() {
         let cmd = instr("col");
         assert_snapshot!(cmd("", &[]), @"Error: Instruction 'col' requires 1 operands, got 0");
         assert_snapshot!(cmd("", &["r1", "r2"]), @"Error: Instruction 'col' requires 1 operands, got 2");
-        assert_snapshot!(cmd("", &["r1"]), @"Error: Invalid immediate: r1");
+        assert_snapshot!(cmd("", &["r1"]), @"Error: Expected a color, found register: r1 (the ISA has no register form of 'col')");
         assert_snapshot!(cmd("", &["0x1FFFFFF"]), @"Error: Immediate value '33554431' out of range for C-type instruction 'col', expected 0 ~ 0xFFFFFF");
         assert_snapshot!(cmd("ne", &["0x123456"]), @"Error: Condition is not allowed for C-type instruction 'col'");
         assert_snapshot!(cmd("", &["0x123456"]), @"1101 000 000 01001 00011 0100010 10110");
     }
+
+    #[test]
+    fn parse_color() {
+        let f = test(super::parse_color);
+        assert_snapshot!(f("#FF8000"), @"16744448");
+        assert_snapshot!(f("#ff8000"), @"16744448");
+        assert_snapshot!(f("#FFF"), @"Error: Invalid color: #FFF (expected '#RRGGBB')");
+        assert_snapshot!(f("rgb(255, 128, 0)"), @"16744448");
+        assert_snapshot!(f("rgb(0x10,0,'A')"), @"1048641");
+        assert_snapshot!(f("rgb(256, 0, 0)"), @"Error: Color channel out of range (0 ~ 255): rgb(256, 0, 0) (red is 256)");
+        assert_snapshot!(f("rgb(0, -1, 0)"), @"Error: Color channel out of range (0 ~ 255): rgb(0, -1, 0) (green is -1)");
+        assert_snapshot!(f("rgb(1, 2)"), @"Error: Invalid color: rgb(1, 2) (expected 'rgb(R, G, B)')");
+        assert_snapshot!(f("orange"), @"16753920");
+        assert_snapshot!(f("0x123456"), @"1193046");
+        assert_snapshot!(f("r2"), @"Error: Expected a color, found register: r2 (the ISA has no register form of 'col')");
+        assert_snapshot!(f("teal"), @"Error: Unknown color: teal (expected one of black, white, gray, red, green, blue, yellow, cyan, magenta, orange, purple, brown, pink)");
+    }
 }
@@ -0,0 +1,532 @@
+use std::{collections::HashSet, fmt::Display};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    analysis::{Cfg, Flow},
+    assembler::Program,
+    instructions::{INSTRUCTIONS, OPCODES, fmt_cond, parse_reg_s},
+    macro_instructions::MACRO_INSTRUCTIONS,
+    pass1::split_comment,
+};
+
+/// A style or correctness rule checked by `lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    /// The warnings of the assembler's analysis.
+    Analysis,
+    /// Constants holding values, not register aliases, should be uppercase.
+    ConstCase,
+    /// Indentation should use either tabs or spaces, not both.
+    MixedIndent,
+    /// Comments should all start with the marker of the first comment, `#` or `;`.
+    CommentStyle,
+    /// Predicated words whose condition is known to always or never hold.
+    RedundantCondition,
+    /// Jumps and branches to the next word.
+    BranchToNext,
+    /// Immediate forms like `addi` that auto-imm would pick for `add` anyway.
+    ExplicitImm,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::Analysis,
+        Rule::ConstCase,
+        Rule::MixedIndent,
+        Rule::CommentStyle,
+        Rule::RedundantCondition,
+        Rule::BranchToNext,
+        Rule::ExplicitImm,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Analysis => "analysis",
+            Rule::ConstCase => "const-case",
+            Rule::MixedIndent => "mixed-indent",
+            Rule::CommentStyle => "comment-style",
+            Rule::RedundantCondition => "redundant-condition",
+            Rule::BranchToNext => "branch-to-next",
+            Rule::ExplicitImm => "explicit-imm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of each rule, all rules warn by default.
+///
+/// A config file sets levels with lines like `branch-to-next = deny` or `all = allow`,
+/// `#` starts a comment. Inside a source file, a `; lint: allow const-case, mixed-indent`
+/// comment applies to the rest of the file, or only to its line when it follows code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    levels: [Level; Rule::ALL.len()],
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            levels: [Level::Warn; Rule::ALL.len()],
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = LintConfig::default();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = match line.split_once('=') {
+                Some((rule, level)) => config.set(&[rule.trim()], level.trim()),
+                None => Err(anyhow!("Expected 'rule = level'")),
+            };
+            result.map_err(|e| {
+                anyhow!(
+                    "Error in lint config at line {}: '{}' ({})",
+                    idx + 1,
+                    line,
+                    e
+                )
+            })?;
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, rules: &[&str], level: &str) -> Result<()> {
+        let level = match level {
+            "allow" => Level::Allow,
+            "warn" => Level::Warn,
+            "deny" => Level::Deny,
+            _ => bail!("Invalid lint level: '{}'", level),
+        };
+
+        for &name in rules {
+            if name == "all" {
+                self.levels = [level; Rule::ALL.len()];
+                continue;
+            }
+
+            let rule = Rule::ALL
+                .into_iter()
+                .find(|rule| rule.name() == name)
+                .ok_or_else(|| anyhow!("Unknown lint rule: '{}'", name))?;
+            self.levels[rule as usize] = level;
+        }
+
+        Ok(())
+    }
+
+    fn level(&self, rule: Rule) -> Level {
+        self.levels[rule as usize]
+    }
+
+    /// The config in effect at each source line, after the inline `lint:` comments.
+    fn per_line(&self, source_lines: &[String]) -> Result<Vec<LintConfig>> {
+        let mut current = self.clone();
+        let mut configs = Vec::new();
+
+        for (idx, line) in source_lines.iter().enumerate() {
+            let (code, comment) = split_comment(line);
+            let Some(directive) = comment.and_then(|(_, text)| text.trim().strip_prefix("lint:"))
+            else {
+                configs.push(current.clone());
+                continue;
+            };
+
+            let tokens = directive
+                .split([' ', '\t', ','])
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>();
+            let mut config = current.clone();
+            match tokens.split_first() {
+                Some((level, rules)) if !rules.is_empty() => config.set(rules, level),
+                _ => Err(anyhow!("Expected 'lint: LEVEL RULE...'")),
+            }
+            .map_err(|e| {
+                anyhow!(
+                    "Error in lint comment at line {}: '{}' ({})",
+                    idx + 1,
+                    line.trim(),
+                    e
+                )
+            })?;
+
+            if code.trim().is_empty() {
+                current = config.clone();
+            }
+            configs.push(config);
+        }
+
+        Ok(configs)
+    }
+}
+
+/// A violation of a rule, at the level configured for its line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub level: Level,
+    /// Index of the source line.
+    pub line: usize,
+    pub source: String,
+    pub message: String,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}: '{}' ({}) [{}]",
+            if self.level == Level::Deny {
+                "Error"
+            } else {
+                "Warning"
+            },
+            self.line + 1,
+            self.source,
+            self.message,
+            self.rule.name()
+        )
+    }
+}
+
+struct Linter<'a> {
+    program: &'a Program,
+    source_lines: &'a [String],
+    /// `(rule, line, message)` of each violation.
+    found: Vec<(Rule, usize, String)>,
+}
+
+/// Checks `program` and its source against every rule that isn't allowed, returns the
+/// lints ordered by line.
+pub fn lint(
+    program: &Program,
+    source_lines: &[String],
+    config: &LintConfig,
+    disable_macro: bool,
+) -> Result<Vec<Lint>> {
+    let configs = config.per_line(source_lines)?;
+
+    let mut linter = Linter {
+        program,
+        source_lines,
+        found: Vec::new(),
+    };
+    linter.analysis();
+    linter.const_case();
+    linter.mixed_indent();
+    linter.comment_style();
+    let cfg = Cfg::new(program);
+    linter.redundant_condition(&cfg);
+    linter.branch_to_next(&cfg);
+    if !disable_macro {
+        linter.explicit_imm();
+    }
+
+    let mut found = linter.found;
+    found.sort_by(|(a, line_a, _), (b, line_b, _)| (line_a, a).cmp(&(line_b, b)));
+    found.dedup();
+
+    Ok(found
+        .into_iter()
+        .filter_map(|(rule, line, message)| {
+            let level = configs.get(line).unwrap_or(config).level(rule);
+            (level != Level::Allow).then(|| Lint {
+                rule,
+                level,
+                line,
+                source: source_lines[line].trim().to_string(),
+                message,
+            })
+        })
+        .collect())
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, line: usize, message: String) {
+        self.found.push((rule, line, message));
+    }
+
+    fn analysis(&mut self) {
+        for warning in &self.program.warnings {
+            self.report(Rule::Analysis, warning.line, warning.message.clone());
+        }
+    }
+
+    fn const_case(&mut self) {
+        for (name, value) in &self.program.constants {
+            if parse_reg_s(&value.as_str().into()).is_ok() || !name.chars().any(char::is_lowercase)
+            {
+                continue;
+            }
+            let line = self.program.definitions[name].0;
+            self.report(
+                Rule::ConstCase,
+                line,
+                format!("constant '{}' should be uppercase", name),
+            );
+        }
+    }
+
+    fn mixed_indent(&mut self) {
+        let name = |c: char| if c == '\t' { "tabs" } else { "spaces" };
+        let mut first: Option<(char, usize)> = None;
+
+        for (idx, line) in self.source_lines.iter().enumerate() {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            if line.trim().is_empty() || indent.is_empty() {
+                continue;
+            }
+            if indent.contains(' ') && indent.contains('\t') {
+                self.report(
+                    Rule::MixedIndent,
+                    idx,
+                    "indentation mixes tabs and spaces".to_string(),
+                );
+                continue;
+            }
+
+            let c = if indent.starts_with('\t') { '\t' } else { ' ' };
+            match first {
+                None => first = Some((c, idx)),
+                Some((f, at)) if f != c => self.report(
+                    Rule::MixedIndent,
+                    idx,
+                    format!(
+                        "indented with {} while line {} uses {}",
+                        name(c),
+                        at + 1,
+                        name(f)
+                    ),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    fn comment_style(&mut self) {
+        let mut first: Option<(char, usize)> = None;
+
+        for (idx, line) in self.source_lines.iter().enumerate() {
+            let Some((marker, _)) = split_comment(line).1 else {
+                continue;
+            };
+            match first {
+                None => first = Some((marker, idx)),
+                Some((f, at)) if f != marker => self.report(
+                    Rule::CommentStyle,
+                    idx,
+                    format!("'{}' comment while line {} uses '{}'", marker, at + 1, f),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    /// Tracks which outcomes of the last comparison are still possible along straight-line
+    /// code, a predicated `jmp` or `ret` falling through rules out its condition. Anything
+    /// is possible again at labels and at the targets of jumps, branches and calls.
+    fn redundant_condition(&mut self, cfg: &Cfg) {
+        const ALL: u8 = 0b111;
+        let outcomes = |cond: u32| match fmt_cond(cond) {
+            Some("eq") => 0b010,
+            Some("ne") => 0b101,
+            Some("lt") => 0b001,
+            Some("ge") => 0b110,
+            Some("gt") => 0b100,
+            Some("le") => 0b011,
+            _ => ALL,
+        };
+
+        let targets = (0..self.program.codes.len())
+            .filter_map(|addr| match cfg.flow(addr) {
+                Flow::Jump(target) | Flow::Branch(target) | Flow::Call(target) => Some(target),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let mut possible = ALL;
+
+        for (addr, &code) in self.program.codes.iter().enumerate() {
+            if self.program.labels.contains_right(&addr) || targets.contains(&addr) {
+                possible = ALL;
+            }
+            let Some(instr) = OPCODES.get(&(code >> 25)) else {
+                continue;
+            };
+            let cond = instr.decode(code).0;
+
+            if cond != 0 {
+                let holds = outcomes(cond);
+                let name = fmt_cond(cond).unwrap_or_default();
+                let line = self.program.source_map[addr].0;
+                if possible & !holds == 0 {
+                    self.report(
+                        Rule::RedundantCondition,
+                        line,
+                        format!("the condition '.{}' always holds here", name),
+                    );
+                } else if possible & holds == 0 {
+                    self.report(
+                        Rule::RedundantCondition,
+                        line,
+                        format!("the condition '.{}' never holds here", name),
+                    );
+                }
+            }
+
+            possible = match cfg.flow(addr) {
+                _ if matches!(instr.name(), "cmp" | "cmpi") => ALL,
+                Flow::Call(_) => ALL,
+                Flow::Jump(_) | Flow::Branch(_) | Flow::Ret if cond != 0 => {
+                    possible & !outcomes(cond)
+                }
+                _ => possible,
+            };
+        }
+    }
+
+    fn branch_to_next(&mut self, cfg: &Cfg) {
+        for addr in 0..self.program.codes.len() {
+            if let Flow::Jump(target) | Flow::Branch(target) = cfg.flow(addr)
+                && target == addr + 1
+            {
+                self.report(
+                    Rule::BranchToNext,
+                    self.program.source_map[addr].0,
+                    "jumps to the next instruction".to_string(),
+                );
+            }
+        }
+    }
+
+    fn explicit_imm(&mut self) {
+        for (idx, line) in self.source_lines.iter().enumerate() {
+            let mut tokens = split_comment(line).0.split_whitespace();
+            let Some(mut first) = tokens.next() else {
+                continue;
+            };
+            if first.ends_with(':') {
+                let Some(next) = tokens.next() else {
+                    continue;
+                };
+                first = next;
+            }
+
+            let name = first.split('.').next().unwrap_or_default();
+            if let Some(base) = name.strip_suffix('i')
+                && MACRO_INSTRUCTIONS
+                    .get(base)
+                    .is_some_and(|mc| mc.imm_form() == Some(name))
+                && INSTRUCTIONS.contains_key(name)
+            {
+                self.report(
+                    Rule::ExplicitImm,
+                    idx,
+                    format!("'{}' picks '{}' by itself for an immediate", base, name),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    fn f(src: &str, config: &str) -> String {
+        let program = assemble(src);
+        let source_lines = src.lines().map(|s| s.to_string()).collect::<Vec<_>>();
+        let config = match LintConfig::parse(config) {
+            Ok(config) => config,
+            Err(e) => return format!("Error: {e}"),
+        };
+
+        match lint(&program, &source_lines, &config, false) {
+            Ok(lints) => lints
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(e) => format!("Error: {e}"),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let src = "
+            const size 12
+            const x r1
+            # entry
+            main:
+                li x size
+                cmpi x 0 ; zero?
+                jmp.eq done
+                li.ne x 1
+                mv.eq io x
+            \tjmp done
+            done:
+                mv io x
+            halt:
+                jmp halt
+        ";
+
+        assert_snapshot!(f(src, ""), @r"
+        Warning at line 2: 'const size 12' (constant 'size' should be uppercase) [const-case]
+        Warning at line 7: 'cmpi x 0 ; zero?' (';' comment while line 4 uses '#') [comment-style]
+        Warning at line 7: 'cmpi x 0 ; zero?' ('cmp' picks 'cmpi' by itself for an immediate) [explicit-imm]
+        Warning at line 9: 'li.ne x 1' (the condition '.ne' always holds here) [redundant-condition]
+        Warning at line 10: 'mv.eq io x' (the condition '.eq' never holds here) [redundant-condition]
+        Warning at line 11: 'jmp done' (indentation mixes tabs and spaces) [mixed-indent]
+        Warning at line 11: 'jmp done' (jumps to the next instruction) [branch-to-next]
+        ");
+
+        let src = "main:\n  cmp r1 r2\n  jmp.eq 1f\n  li r3 1\n1: mv.eq r4 r5\n  jmp.ne .l\n.l: mv.ne r4 r5";
+        assert_snapshot!(f(src, "analysis = allow\nbranch-to-next = allow"), @"");
+    }
+
+    #[test]
+    fn config() {
+        let src = "
+            const size 12 # lint: allow const-case
+            ; lint: deny branch-to-next, redundant-condition
+            main:
+                li r1 size
+                cmp r1 r1
+                jmp.eq next
+            next:
+                jmp halt
+            halt:
+                jmp.ne halt
+                mv.ne io r1
+        ";
+
+        assert_snapshot!(f(src, "comment-style = deny # mixed"), @r"
+        Error at line 3: '; lint: deny branch-to-next, redundant-condition' (';' comment while line 2 uses '#') [comment-style]
+        Error at line 7: 'jmp.eq next' (jumps to the next instruction) [branch-to-next]
+        Error at line 9: 'jmp halt' (jumps to the next instruction) [branch-to-next]
+        Error at line 12: 'mv.ne io r1' (the condition '.ne' never holds here) [redundant-condition]
+        ");
+        assert_snapshot!(f(src, "all = allow"), @r"
+        Error at line 7: 'jmp.eq next' (jumps to the next instruction) [branch-to-next]
+        Error at line 9: 'jmp halt' (jumps to the next instruction) [branch-to-next]
+        Error at line 12: 'mv.ne io r1' (the condition '.ne' never holds here) [redundant-condition]
+        ");
+        assert_snapshot!(f(src, "branch-to-next"), @"Error: Error in lint config at line 1: 'branch-to-next' (Expected 'rule = level')");
+        assert_snapshot!(f(src, "typo = warn"), @"Error: Error in lint config at line 1: 'typo = warn' (Unknown lint rule: 'typo')");
+        assert_snapshot!(f("main: ; lint: warn", ""), @"Error: Error in lint comment at line 1: 'main: ; lint: warn' (Expected 'lint: LEVEL RULE...')");
+    }
+}
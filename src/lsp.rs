@@ -0,0 +1,654 @@
+mod document;
+mod transport;
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    process::ExitCode,
+};
+
+use anyhow::Result;
+use serde_json::{Value, json};
+
+use crate::{
+    instructions::{INSTRUCTIONS, PALETTE, fmt_cond, fmt_reg},
+    lsp::{
+        document::{Document, Role, Token},
+        transport::{read_message, write_message},
+    },
+    macro_instructions::MACRO_INSTRUCTIONS,
+    operand::{OperandType, OperandValue},
+    pass1::tokenize,
+    pseudo_instructions::PSEUDO_INSTRUCTIONS,
+    utils::fmt_line,
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+const SEVERITY_ERROR: u32 = 1;
+const SEVERITY_WARNING: u32 = 2;
+
+/// The directives, completed along with the mnemonics.
+const DIRECTIVES: [&str; 23] = [
+    "const",
+    ".set",
+    ".func",
+    ".endfunc",
+    ".if",
+    ".elif",
+    ".else",
+    ".endif",
+    ".ifdef",
+    ".ifndef",
+    ".rept",
+    ".irp",
+    ".endr",
+    ".while",
+    ".endwhile",
+    ".for",
+    ".endfor",
+    ".break",
+    ".continue",
+    ".struct",
+    ".endstruct",
+    ".enum",
+    ".endenum",
+];
+const SIGNATURE_KEYWORDS: [&str; 4] = ["in", "out", "clobbers", "saves"];
+
+/// A language server for ArchP assembly, speaking JSON-RPC over `output`.
+///
+/// Documents are synchronized in full, every change assembles the document again to
+/// publish the errors of both passes and the warnings of the analysis.
+pub struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, Document>,
+    disable_macro: bool,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(output: W) -> Self {
+        Server {
+            output,
+            documents: HashMap::new(),
+            disable_macro: false,
+            shutdown: false,
+        }
+    }
+
+    /// Serves until the `exit` notification or the end of `input`, the exit code tells
+    /// whether `shutdown` was requested first.
+    pub fn run(&mut self, mut input: impl BufRead) -> Result<ExitCode> {
+        while let Some(message) = read_message(&mut input)? {
+            if message["method"] == "exit" {
+                break;
+            }
+            self.handle(&message)?;
+        }
+
+        Ok(if self.shutdown {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        })
+    }
+
+    fn handle(&mut self, message: &Value) -> Result<()> {
+        let Some(method) = message["method"].as_str() else {
+            // A response, the server sends no requests.
+            return Ok(());
+        };
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+
+        let response = match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        write_message(&mut self.output, &response)
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.update(uri, text)
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|changes| changes.last()) {
+                    Some(change) => self.update(uri, change["text"].as_str().unwrap_or_default()),
+                    None => Ok(()),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish(uri, Vec::new())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "The server is shutting down".to_string()));
+        }
+
+        match method {
+            "initialize" => {
+                self.disable_macro = params["initializationOptions"]["disableMacro"]
+                    .as_bool()
+                    .unwrap_or_default();
+                return Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "completionProvider": { "triggerCharacters": ["."] },
+                        "documentSymbolProvider": true,
+                    },
+                    "serverInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }));
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                return Ok(Value::Null);
+            }
+            _ => {}
+        }
+
+        let document = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str().unwrap_or_default());
+        let position = &params["position"];
+        let (line, character) = (
+            position["line"].as_u64().unwrap_or_default() as usize,
+            position["character"].as_u64().unwrap_or_default() as usize,
+        );
+        let token = document.and_then(|document| document.token_at(line, character));
+
+        Ok(match method {
+            "textDocument/hover" => match (document, token) {
+                (Some(document), Some(token)) => {
+                    hover(document, token).map_or(Value::Null, |text| {
+                        json!({
+                            "contents": { "kind": "markdown", "value": text },
+                            "range": range(token),
+                        })
+                    })
+                }
+                _ => Value::Null,
+            },
+            "textDocument/definition" => {
+                let uri = &params["textDocument"]["uri"];
+                match (document, token) {
+                    (Some(document), Some(token)) if token.role != Role::Mnemonic => {
+                        document.definition(&token.text, token.line).map_or(
+                            Value::Null,
+                            |def| json!({ "uri": uri, "range": range(def) }),
+                        )
+                    }
+                    _ => Value::Null,
+                }
+            }
+            "textDocument/references" => {
+                let uri = &params["textDocument"]["uri"];
+                let declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                match (document, token) {
+                    (Some(document), Some(token))
+                        if document.definition(&token.text, token.line).is_some() =>
+                    {
+                        document
+                            .references(&token.text)
+                            .filter(|t| declaration || t.role == Role::Operand)
+                            .map(|t| json!({ "uri": uri, "range": range(t) }))
+                            .collect()
+                    }
+                    _ => Value::Null,
+                }
+            }
+            "textDocument/completion" => match document {
+                Some(document) => completion(document, line, character).into(),
+                None => Value::Null,
+            },
+            "textDocument/documentSymbol" => match document {
+                Some(document) => symbols(document).into(),
+                None => Value::Null,
+            },
+            _ => return Err((METHOD_NOT_FOUND, format!("Unknown method: '{}'", method))),
+        })
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> Result<()> {
+        let document = Document::new(text, self.disable_macro);
+        let diagnostics = diagnostics(&document);
+        self.documents.insert(uri.to_string(), document);
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Value>) -> Result<()> {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        });
+        write_message(&mut self.output, &notification)
+    }
+}
+
+/// The error of the assembler, or the warnings of the analysis.
+fn diagnostics(document: &Document) -> Vec<Value> {
+    let diagnostic = |line: usize, severity, message: &str| {
+        json!({
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line, "character": document.line_len(line) },
+            },
+            "severity": severity,
+            "source": env!("CARGO_PKG_NAME"),
+            "message": message,
+        })
+    };
+
+    match &document.program {
+        Ok(program) => program
+            .warnings
+            .iter()
+            .map(|w| diagnostic(w.line, SEVERITY_WARNING, &w.message))
+            .collect(),
+        Err(e) => {
+            let message = e.to_string();
+            vec![diagnostic(
+                error_line(&message).unwrap_or_default(),
+                SEVERITY_ERROR,
+                &message,
+            )]
+        }
+    }
+}
+
+/// The index of the line an error of the assembler is at, as in `Error encoding line 3: ...`.
+fn error_line(message: &str) -> Option<usize> {
+    message.match_indices("line ").find_map(|(idx, _)| {
+        let rest = &message[idx + "line ".len()..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..digits].parse::<usize>().ok()?.checked_sub(1)
+    })
+}
+
+fn hover(document: &Document, token: &Token) -> Option<String> {
+    if token.role == Role::Mnemonic {
+        return hover_mnemonic(document, token);
+    }
+
+    let def = document.definition(&token.text, token.line)?;
+    match def.role {
+        Role::Constant => Some(format!(
+            "```\nconst {} {}\n```",
+            def.text,
+            document.value(def)
+        )),
+        _ => {
+            let mut text = format!("label `{}`", def.text);
+            if let Ok(program) = &document.program {
+                if let Some(addr) = program.labels.get_by_left(&def.text) {
+                    text += &format!(" at `0x{:04X}`", addr);
+                }
+                if let Some(signature) = program.signatures.get(&def.text) {
+                    text += &format!("\n```\n.func {} {}\n```", def.text, signature);
+                }
+            }
+            Some(text)
+        }
+    }
+}
+
+/// The encoding of an instruction, and the expansion of the line if it is a macro- or a
+/// pseudo-instruction.
+fn hover_mnemonic(document: &Document, token: &Token) -> Option<String> {
+    let (name, cond) = match token.text.split_once('.') {
+        Some((name, cond)) if !name.is_empty() => (name, Some(cond)),
+        _ => (token.text.as_str(), None),
+    };
+
+    let ops = document
+        .tokens
+        .iter()
+        .filter(|t| t.line == token.line && t.role == Role::Operand)
+        .map(|t| match document.definition(&t.text, t.line) {
+            Some(def) if def.role == Role::Constant => OperandValue::from(document.value(def)),
+            _ => OperandValue::from(t.text.as_str()),
+        })
+        .collect::<Vec<_>>();
+
+    let mut sections = Vec::new();
+    if let Some(instr) = INSTRUCTIONS.get(name) {
+        let types = instr
+            .get_operand_types()
+            .iter()
+            .map(|ty| match ty {
+                OperandType::RegD => "rd".to_string(),
+                OperandType::RegS => "rs".to_string(),
+                OperandType::Imm(range) => format!("imm({})", range),
+                OperandType::Color => "color".to_string(),
+            })
+            .collect::<Vec<_>>();
+        sections.push(format!(
+            "```\n{} {}\n```\n{}-type instruction, opcode `0b{:07b}`",
+            name,
+            types.join(" "),
+            instr.itype(),
+            instr.opcode()
+        ));
+    }
+
+    let expanded = if let Some(mc_instr) = MACRO_INSTRUCTIONS
+        .get(name)
+        .filter(|_| !document.disable_macro)
+    {
+        match mc_instr.expand(cond, &ops) {
+            Ok(Some(lines)) => Some(
+                lines
+                    .into_iter()
+                    .map(|(name, cond, ops)| fmt_line(name, cond, ops))
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        }
+    } else if let Some(ps_instr) = PSEUDO_INSTRUCTIONS.get(name) {
+        ps_instr
+            .expand(&ops)
+            .ok()
+            .map(|(name, ops)| vec![fmt_line(name, cond, ops)])
+    } else {
+        None
+    };
+    if let Some(lines) = expanded {
+        sections.push(format!("Expands to:\n```\n{}\n```", lines.join("\n")));
+    }
+
+    (!sections.is_empty()).then(|| sections.join("\n\n"))
+}
+
+fn completion(document: &Document, line: usize, character: usize) -> Vec<Value> {
+    const KEYWORD: u32 = 14;
+    const ENUM_MEMBER: u32 = 20;
+    const VARIABLE: u32 = 6;
+    const CONSTANT: u32 = 21;
+    const FUNCTION: u32 = 3;
+    const REFERENCE: u32 = 18;
+    const COLOR: u32 = 16;
+
+    let Some(code) = document.code_before(line, character) else {
+        return Vec::new();
+    };
+    let item =
+        |label: &str, kind, detail: &str| json!({ "label": label, "kind": kind, "detail": detail });
+
+    let mut words = tokenize(code);
+    if code.ends_with(char::is_whitespace) || words.is_empty() {
+        words.push("");
+    }
+    if words[0].ends_with(':') {
+        words.remove(0);
+    }
+    let (current, before) = words.split_last().unwrap_or((&"", &[]));
+
+    if before.is_empty() {
+        if current.contains('.') && !current.starts_with('.') {
+            return (1..8)
+                .filter_map(fmt_cond)
+                .map(|cond| item(cond, ENUM_MEMBER, "condition"))
+                .collect();
+        }
+
+        let mut items = Vec::new();
+        for (&name, instr) in INSTRUCTIONS.iter() {
+            items.push(item(
+                name,
+                KEYWORD,
+                &format!("{}-type instruction", instr.itype()),
+            ));
+        }
+        for &name in MACRO_INSTRUCTIONS.keys() {
+            if !INSTRUCTIONS.contains_key(name) {
+                items.push(item(name, KEYWORD, "macro-instruction"));
+            }
+        }
+        for &name in PSEUDO_INSTRUCTIONS.keys() {
+            items.push(item(name, KEYWORD, "pseudo-instruction"));
+        }
+        for name in DIRECTIVES {
+            items.push(item(name, KEYWORD, "directive"));
+        }
+        items.sort_by(|a, b| a["label"].as_str().cmp(&b["label"].as_str()));
+        return items;
+    }
+
+    let registers = (0..32).map(|reg| item(fmt_reg(reg), VARIABLE, "register"));
+    let mut constants = document.constants.iter().collect::<Vec<_>>();
+    constants.sort();
+    let constants = constants
+        .into_iter()
+        .map(|(name, value)| item(name, CONSTANT, value));
+    // Anonymous labels are left out, and local labels offered only in their scope.
+    let scope = document.scope(line).unwrap_or_default();
+    let labels = document.labels().filter_map(|label| {
+        let name = match label.text.split_once('.') {
+            Some((global, _)) if global == scope => &label.text[global.len()..],
+            Some(_) => return None,
+            None if label.text.starts_with(|c: char| c.is_ascii_digit()) => return None,
+            None => &label.text,
+        };
+        let kind = if document.is_function(name) {
+            FUNCTION
+        } else {
+            REFERENCE
+        };
+        Some(item(name, kind, "label"))
+    });
+
+    match (before[0], before.len()) {
+        // The name of a new constant.
+        ("const" | ".set", 1) => Vec::new(),
+        ("const" | ".set", _) => registers.chain(constants).collect(),
+        (".func", 1) => labels.collect(),
+        (".func", _) => SIGNATURE_KEYWORDS
+            .into_iter()
+            .map(|keyword| item(keyword, KEYWORD, "signature"))
+            .chain(registers)
+            .chain(constants)
+            .collect(),
+        ("col", _) => PALETTE
+            .iter()
+            .map(|&(name, color)| item(name, COLOR, &format!("#{:06X}", color)))
+            .chain(constants)
+            .collect(),
+        _ => registers.chain(constants).chain(labels).collect(),
+    }
+}
+
+fn symbols(document: &Document) -> Vec<Value> {
+    const FUNCTION: u32 = 12;
+    const CONSTANT: u32 = 14;
+    const KEY: u32 = 20;
+
+    document
+        .tokens
+        .iter()
+        .filter_map(|token| {
+            let (kind, detail) = match token.role {
+                Role::Constant => (CONSTANT, document.value(token).to_string()),
+                Role::Label if document.is_function(&token.text) => {
+                    let signature = document.program.as_ref().ok().and_then(|program| {
+                        program.signatures.get(&token.text).map(|s| s.to_string())
+                    });
+                    (FUNCTION, signature.unwrap_or_default())
+                }
+                Role::Label => (KEY, String::new()),
+                _ => return None,
+            };
+            Some(json!({
+                "name": token.text,
+                "detail": detail,
+                "kind": kind,
+                "range": {
+                    "start": { "line": token.line, "character": 0 },
+                    "end": { "line": token.line, "character": document.line_len(token.line) },
+                },
+                "selectionRange": range(token),
+            }))
+        })
+        .collect()
+}
+
+fn range(token: &Token) -> Value {
+    json!({
+        "start": { "line": token.line, "character": token.start },
+        "end": { "line": token.line, "character": token.end },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::from_fn;
+
+    use super::*;
+    use crate::testkit::*;
+
+    const URI: &str = "file:///main.asm";
+
+    fn session(messages: &[Value]) -> (ExitCode, Vec<Value>) {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let code = Server::new(&mut output).run(input.as_slice()).unwrap();
+        let mut output = output.as_slice();
+        (
+            code,
+            from_fn(|| read_message(&mut output).unwrap()).collect(),
+        )
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    #[test]
+    fn server() {
+        let src = "\
+const a0 r1
+main:
+  li a0 0x12345
+  call.eq double
+  mv io r13
+  jmp main
+.func double in a0 out r13
+double:
+  add r13 a0 a0
+  ret
+";
+        let (code, responses) = session(&[
+            request(1, "initialize", 0, 0),
+            notification("initialized", json!({})),
+            notification(
+                "textDocument/didOpen",
+                json!({ "textDocument": { "uri": URI, "languageId": "archp", "version": 1, "text": src } }),
+            ),
+            request(2, "textDocument/hover", 2, 3),
+            request(3, "textDocument/hover", 3, 12),
+            request(4, "textDocument/definition", 8, 12),
+            request(5, "textDocument/references", 7, 2),
+            request(6, "textDocument/completion", 3, 7),
+            request(7, "textDocument/completion", 4, 9),
+            request(8, "textDocument/documentSymbol", 0, 0),
+            request(9, "textDocument/formatting", 0, 0),
+            notification(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": "main:\n  foo r1\n" }],
+                }),
+            ),
+            request(10, "shutdown", 0, 0),
+            notification("exit", Value::Null),
+        ]);
+
+        let lines = responses
+            .iter()
+            .map(|message| {
+                let body = match message.get("result") {
+                    Some(Value::Array(items))
+                        if items.iter().all(|item| item.get("label").is_some()) =>
+                    {
+                        let labels = items
+                            .iter()
+                            .map(|item| item["label"].as_str().unwrap_or_default())
+                            .collect::<Vec<_>>();
+                        json!(labels.join(" "))
+                    }
+                    Some(result) => result.clone(),
+                    None => message
+                        .get("error")
+                        .or(message.get("params"))
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                let kind = message
+                    .get("id")
+                    .map_or(message["method"].clone(), |id| id.clone());
+                format!("{}: {}", kind, body)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_snapshot!(lines.join("\n"), @r#"
+        1: {"capabilities":{"completionProvider":{"triggerCharacters":["."]},"definitionProvider":true,"documentSymbolProvider":true,"hoverProvider":true,"referencesProvider":true,"textDocumentSync":1},"serverInfo":{"name":"archp_asmc","version":"0.1.0"}}
+        "textDocument/publishDiagnostics": {"diagnostics":[{"message":"no comparison sets the flags on some path","range":{"end":{"character":16,"line":3},"start":{"character":0,"line":3}},"severity":2,"source":"archp_asmc"}],"uri":"file:///main.asm"}
+        2: {"contents":{"kind":"markdown","value":"```\nli rd imm(0 ~ 0xFFF)\n```\nI-type instruction, opcode `0b1000010`\n\nExpands to:\n```\nlui r1 18\nori r1 r1 0x345\n```"},"range":{"end":{"character":4,"line":2},"start":{"character":2,"line":2}}}
+        3: {"contents":{"kind":"markdown","value":"label `double` at `0x0005`\n```\n.func double in a0 out r13\n```"},"range":{"end":{"character":16,"line":3},"start":{"character":10,"line":3}}}
+        4: {"range":{"end":{"character":8,"line":0},"start":{"character":6,"line":0}},"uri":"file:///main.asm"}
+        5: [{"range":{"end":{"character":16,"line":3},"start":{"character":10,"line":3}},"uri":"file:///main.asm"},{"range":{"end":{"character":12,"line":6},"start":{"character":6,"line":6}},"uri":"file:///main.asm"},{"range":{"end":{"character":6,"line":7},"start":{"character":0,"line":7}},"uri":"file:///main.asm"}]
+        6: "eq ne lt ge gt le"
+        7: "zero r1 r2 r3 r4 r5 r6 r7 r8 r9 r10 r11 r12 r13 r14 r15 r16 r17 r18 r19 r20 r21 r22 r23 r24 pc io kb rng r29 r30 tmp a0 main double"
+        8: [{"detail":"r1","kind":14,"name":"a0","range":{"end":{"character":11,"line":0},"start":{"character":0,"line":0}},"selectionRange":{"end":{"character":8,"line":0},"start":{"character":6,"line":0}}},{"detail":"","kind":20,"name":"main","range":{"end":{"character":5,"line":1},"start":{"character":0,"line":1}},"selectionRange":{"end":{"character":4,"line":1},"start":{"character":0,"line":1}}},{"detail":"in a0 out r13","kind":12,"name":"double","range":{"end":{"character":7,"line":7},"start":{"character":0,"line":7}},"selectionRange":{"end":{"character":6,"line":7},"start":{"character":0,"line":7}}}]
+        9: {"code":-32601,"message":"Unknown method: 'textDocument/formatting'"}
+        "textDocument/publishDiagnostics": {"diagnostics":[{"message":"Error encoding line 2: 'foo r1' (Unknown instruction: 'foo')","range":{"end":{"character":8,"line":1},"start":{"character":0,"line":1}},"severity":1,"source":"archp_asmc"}],"uri":"file:///main.asm"}
+        10: null
+        "#);
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{
+    assembler::{Assembler, AssemblerSettings, Program},
+    pass1::{split_comment, split_words},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// An instruction or a directive, with its condition.
+    Mnemonic,
+    /// The definition of a constant.
+    Constant,
+    /// The definition of a label.
+    Label,
+    Operand,
+}
+
+/// A word of the source, with its columns in UTF-16 code units as counted by LSP.
+///
+/// Local labels like `.loop` are named after the global label they belong to, `main.loop`.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub role: Role,
+}
+
+/// An open source file, indexed by the same tokenizer as `Pass1`.
+pub struct Document {
+    pub lines: Vec<String>,
+    pub tokens: Vec<Token>,
+    /// The value of each constant as written, the last one if it is defined several times.
+    pub constants: HashMap<String, String>,
+    pub disable_macro: bool,
+    pub program: Result<Program>,
+}
+
+impl Document {
+    pub fn new(text: &str, disable_macro: bool) -> Self {
+        let lines = text.lines().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let mut tokens = Vec::new();
+        let mut constants = HashMap::new();
+        let mut scope = String::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let words = words(line);
+            let Some(&(_, first)) = words.first() else {
+                continue;
+            };
+
+            let mut roles = vec![Role::Operand; words.len()];
+            if first == "const" || first == ".set" {
+                roles[0] = Role::Mnemonic;
+                if let Some(&(_, name)) = words.get(1) {
+                    roles[1] = Role::Constant;
+                    let value = words.get(2).map_or("", |&(_, value)| value);
+                    constants.insert(name.to_string(), value.to_string());
+                }
+            } else {
+                let mnemonic = usize::from(first.ends_with(':'));
+                if mnemonic == 1 {
+                    roles[0] = Role::Label;
+                }
+                if let Some(role) = roles.get_mut(mnemonic) {
+                    *role = Role::Mnemonic;
+                }
+            }
+
+            for ((offset, word), role) in words.into_iter().zip(roles) {
+                let word = match role {
+                    Role::Label => word.trim_end_matches(':'),
+                    _ => word,
+                };
+                let text = match role {
+                    Role::Label | Role::Operand if word.starts_with('.') => scope.clone() + word,
+                    Role::Label if !word.starts_with(|c: char| c.is_ascii_digit()) => {
+                        scope = word.to_string();
+                        scope.clone()
+                    }
+                    _ => word.to_string(),
+                };
+                tokens.push(Token {
+                    line: idx,
+                    start: utf16_len(&line[..offset]),
+                    end: utf16_len(&line[..offset + word.len()]),
+                    text,
+                    role,
+                });
+            }
+        }
+
+        let settings = AssemblerSettings {
+            disable_macro,
+            defines: Vec::new(),
+        };
+        let program = Assembler::new(settings, lines.clone()).assemble();
+
+        Document {
+            lines,
+            tokens,
+            constants,
+            disable_macro,
+            program,
+        }
+    }
+
+    /// The token under the cursor, a cursor right after a word is on it.
+    pub fn token_at(&self, line: usize, character: usize) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .find(|t| t.line == line && t.start <= character && character <= t.end)
+    }
+
+    /// The definition of the constant or the label `name` referenced from the line `line`.
+    ///
+    /// As in `Pass1`, a constant is its last definition up to the line under the same global
+    /// label, else before the first label, and `1f` and `1b` are the next and the previous
+    /// `1:`. The fields of a `.struct` and the members of an `.enum` have no definition.
+    pub fn definition(&self, name: &str, line: usize) -> Option<&Token> {
+        if let Some((label, forward)) = name.split_at_checked(name.len().saturating_sub(1))
+            && !label.is_empty()
+            && label.bytes().all(|b| b.is_ascii_digit())
+            && (forward == "f" || forward == "b")
+        {
+            let mut labels = self.labels().filter(|t| t.text == label);
+            return match forward {
+                "f" => labels.find(|t| t.line > line),
+                _ => labels.filter(|t| t.line <= line).last(),
+            };
+        }
+
+        let before = |scope: Option<&str>| {
+            self.tokens
+                .iter()
+                .rev()
+                .filter(|t| t.text == name && t.role == Role::Constant && t.line <= line)
+                .find(|t| self.scope(t.line) == scope)
+        };
+        before(self.scope(line))
+            .or_else(|| before(None))
+            .or_else(|| {
+                self.tokens
+                    .iter()
+                    .find(|t| t.text == name && matches!(t.role, Role::Constant | Role::Label))
+            })
+    }
+
+    /// The value of the constant defined by `def`, as written.
+    pub fn value(&self, def: &Token) -> &str {
+        words(&self.lines[def.line])
+            .get(2)
+            .map_or("", |&(_, value)| value)
+    }
+
+    /// The definition and the uses of `name`.
+    pub fn references(&self, name: &str) -> impl Iterator<Item = &Token> {
+        self.tokens
+            .iter()
+            .filter(move |t| t.text == name && t.role != Role::Mnemonic)
+    }
+
+    /// The labels, in the order they are defined.
+    pub fn labels(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().filter(|t| t.role == Role::Label)
+    }
+
+    /// The global label the line `line` belongs to.
+    pub fn scope(&self, line: usize) -> Option<&str> {
+        self.labels()
+            .take_while(|t| t.line <= line)
+            .filter(|t| !t.text.contains('.') && !t.text.starts_with(|c: char| c.is_ascii_digit()))
+            .last()
+            .map(|t| t.text.as_str())
+    }
+
+    /// Whether the label `name` is called or has a `.func` signature.
+    pub fn is_function(&self, name: &str) -> bool {
+        self.lines.iter().any(|line| match words(line).as_slice() {
+            [(_, ".func"), (_, target), ..] => *target == name,
+            [.., (_, mnemonic), (_, target)] => {
+                *target == name && mnemonic.split('.').next() == Some("call")
+            }
+            _ => false,
+        })
+    }
+
+    /// The code of line `line` before `character`, or `None` if the cursor is in a comment.
+    pub fn code_before(&self, line: usize, character: usize) -> Option<&str> {
+        let line = self.lines.get(line)?;
+        let end = line
+            .char_indices()
+            .scan(0, |units, (idx, c)| {
+                let start = *units;
+                *units += c.len_utf16();
+                Some((idx, start))
+            })
+            .find(|&(_, units)| units >= character)
+            .map_or(line.len(), |(idx, _)| idx);
+
+        let code = split_comment(line).0;
+        (end <= code.len()).then(|| &line[..end])
+    }
+
+    pub fn line_len(&self, line: usize) -> usize {
+        self.lines.get(line).map_or(0, |line| utf16_len(line))
+    }
+}
+
+/// The words of the code of `line` with their byte offsets, operand lists like the
+/// `.func` registers `a0,s0` are split on commas.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    split_words(split_comment(line).0, |c| c.is_whitespace() || c == ',')
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn index() {
+        let document = Document::new(
+            "const a0 r4 ; é\nmain: li a0 1\n  call.eq f\nf:\n.l:\tret # done\n  jmp .l",
+            false,
+        );
+        let tokens = document
+            .tokens
+            .iter()
+            .map(|t| format!("{}:{}-{} {:?} {}", t.line, t.start, t.end, t.role, t.text))
+            .collect::<Vec<_>>();
+
+        assert_snapshot!(tokens.join("\n"), @r"
+        0:0-5 Mnemonic const
+        0:6-8 Constant a0
+        0:9-11 Operand r4
+        1:0-4 Label main
+        1:6-8 Mnemonic li
+        1:9-11 Operand a0
+        1:12-13 Operand 1
+        2:2-9 Mnemonic call.eq
+        2:10-11 Operand f
+        3:0-1 Label f
+        4:0-2 Label f.l
+        4:4-7 Mnemonic ret
+        5:2-5 Mnemonic jmp
+        5:6-8 Operand f.l
+        ");
+        assert!(document.is_function("f"));
+        assert!(!document.is_function("main"));
+        assert_eq!(document.scope(5), Some("f"));
+        assert_eq!(document.references("a0").count(), 2);
+        assert_eq!(document.code_before(1, 8), Some("main: li"));
+        assert_eq!(document.code_before(0, 14), None);
+    }
+
+    #[test]
+    fn definition() {
+        let document = Document::new(
+            "const x 1\nmain:\nconst x 2\nli r1 x\n.set n 1\n.set n 2\nli r2 n\n\
+             1: jmp 1b\njmp 1f\n1: ret\nother:\nli r1 x",
+            false,
+        );
+        let line = |name, line| document.definition(name, line).map(|t| t.line);
+
+        assert_eq!(line("x", 3), Some(2));
+        assert_eq!(line("x", 11), Some(0));
+        assert_eq!(document.value(document.definition("x", 11).unwrap()), "1");
+        assert_eq!(line("n", 4), Some(4));
+        assert_eq!(line("n", 6), Some(5));
+        assert_eq!(document.value(document.definition("n", 6).unwrap()), "2");
+        assert_eq!(line("1b", 7), Some(7));
+        assert_eq!(line("1f", 8), Some(9));
+        assert_eq!(line("1f", 9), None);
+        assert_eq!(line("other", 0), Some(10));
+    }
+}
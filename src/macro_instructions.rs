@@ -3,6 +3,7 @@ mod auto_imm;
 mod branch_imm;
 mod cmp_imm32;
 mod load_imm32;
+mod push_pop_multiple;
 
 use std::collections::{HashMap, VecDeque};
 
@@ -23,9 +24,12 @@ type ExpandFn = for<'a> fn(
 pub struct MacroInstruction {
     name: &'static str,
     operand_count: usize,
+    /// Takes `operand_count` operands or more.
+    variadic: bool,
     expander: ExpandFn,
 
-    _may_be_name_with_i: &'static str,
+    /// None for a variadic macro-instruction, which has no immediate form.
+    _may_be_name_with_i: Option<&'static str>,
 }
 
 inventory::collect!(MacroInstruction);
@@ -75,8 +79,26 @@ impl MacroInstruction {
         Ok(Some(ret))
     }
 
+    /// The instruction taking an immediate that auto-imm may pick, e.g. `addi` for `add`.
+    pub fn imm_form(&self) -> Option<&'static str> {
+        self._may_be_name_with_i
+    }
+
+    /// Whether the operands are a list, which may be written separated by commas.
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
     fn assert_operand_count(&self, operands: &[OperandValue]) -> Result<()> {
-        if operands.len() != self.operand_count {
+        if self.variadic && operands.len() < self.operand_count {
+            bail!(
+                "Macro-instruction '{}' requires at least {} operands, got {}",
+                self.name,
+                self.operand_count,
+                operands.len()
+            );
+        }
+        if !self.variadic && operands.len() != self.operand_count {
             bail!(
                 "Macro-instruction '{}' requires {} operands, got {}",
                 self.name,
@@ -104,6 +126,23 @@ macro macro_instruction {
         )+
     },
 
+    (
+        name: $name:literal,
+        operand_count: $count:literal ..,
+        expander: $expander:expr,
+    ) => {
+        inventory::submit! {
+            $crate::macro_instructions::MacroInstruction {
+                name: $name,
+                operand_count: $count,
+                variadic: true,
+                expander: $expander,
+
+                _may_be_name_with_i: None,
+            }
+        }
+    },
+
     (
         name: $name:literal,
         operand_count: $count:literal,
@@ -113,9 +152,10 @@ macro macro_instruction {
             $crate::macro_instructions::MacroInstruction {
                 name: $name,
                 operand_count: $count,
+                variadic: false,
                 expander: $expander,
 
-                _may_be_name_with_i: concat!($name, "i"),
+                _may_be_name_with_i: Some(concat!($name, "i")),
             }
         }
     },
@@ -22,6 +22,23 @@ const F: ExpandFn = |_, this, cond, ops| {
 
     let imm = parse_imm(&ops[2])?;
 
+    // A negative immediate is added by subtracting its magnitude, and vice versa.
+    let opposite = match this.name {
+        "addi" => Some("subi"),
+        "subi" => Some("addi"),
+        _ => None,
+    };
+    if let Some(opposite) = opposite
+        && imm > 0xFFF
+        && imm.wrapping_neg() <= 0xFFF
+    {
+        return Ok(Some(vec![(
+            opposite,
+            cond,
+            op_values![ops[0], ops[1], imm.wrapping_neg()],
+        )]));
+    }
+
     if imm > 0xFFF {
         Ok(Some(vec![
             ("li", cond, op_values!["tmp", imm]),
@@ -52,5 +69,11 @@ mod tests {
 
         assert_snapshot!(addi("eq", &["r1", "r2", "0x123"]), @"");
         assert_snapshot!(addi("eq", &["r1", "r2", "0x1234"]), @"lui tmp 1; ori tmp tmp 0x234; add.eq r1 r2 tmp");
+
+        assert_snapshot!(addi("", &["r1", "r2", "-1"]), @"subi r1 r2 1");
+        assert_snapshot!(addi("eq", &["r1", "r2", "-0xFFF"]), @"subi.eq r1 r2 0xFFF");
+        assert_snapshot!(addi("", &["r1", "r2", "-0x1000"]), @"lui tmp 0xFFFFF; ori tmp tmp 0; add r1 r2 tmp");
+        assert_snapshot!(mc_instr("subi")("", &["r1", "r2", "-4"]), @"addi r1 r2 4");
+        assert_snapshot!(mc_instr("andi")("", &["r1", "r2", "-4"]), @"subi tmp zero 4; and r1 r2 tmp");
     }
 }
@@ -15,7 +15,9 @@ macro_instruction! {
 }
 
 const F1: ExpandFn = |_, this, cond, ops| {
-    let inst = this._may_be_name_with_i;
+    let Some(inst) = this.imm_form() else {
+        return Ok(None);
+    };
 
     parse_reg_d(&ops[0])?;
     parse_reg_s(&ops[1])?;
@@ -34,7 +36,9 @@ macro_instruction! {
 }
 
 const F2: ExpandFn = |_, this, cond, ops| {
-    let inst = this._may_be_name_with_i;
+    let Some(inst) = this.imm_form() else {
+        return Ok(None);
+    };
 
     parse_reg_s(&ops[0])?;
 
@@ -52,7 +56,9 @@ macro_instruction! {
 }
 
 const F3: ExpandFn = |_, this, cond, ops| {
-    let inst = this._may_be_name_with_i;
+    let Some(inst) = this.imm_form() else {
+        return Ok(None);
+    };
 
     parse_reg_s(&ops[0])?;
 
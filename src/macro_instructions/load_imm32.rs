@@ -15,6 +15,14 @@ pub const F: ExpandFn = |_, _, cond, ops| {
 
     let imm = parse_imm(&ops[1])?;
 
+    if imm > 0xFFF && imm.wrapping_neg() <= 0xFFF {
+        return Ok(Some(vec![(
+            "subi",
+            cond,
+            op_values![ops[0], "zero", imm.wrapping_neg()],
+        )]));
+    }
+
     if imm > 0xFFF {
         if ops[0] != "tmp".into() && cond.is_none() {
             return Ok(Some(vec![
@@ -61,5 +69,9 @@ mod tests {
 
         assert_snapshot!(li("eq", &["r1", "0x123"]), @"");
         assert_snapshot!(li("eq", &["r1", "0x1234"]), @"lui tmp 1; ori tmp tmp 0x234; mv.eq r1 tmp");
+
+        assert_snapshot!(li("", &["r1", "-1"]), @"subi r1 zero 1");
+        assert_snapshot!(li("eq", &["r1", "-0x1000"]), @"lui tmp 0xFFFFF; ori tmp tmp 0; mv.eq r1 tmp");
+        assert_snapshot!(li("", &["r1", "-0x1001"]), @"lui r1 0xFFFFE; ori r1 r1 0xFFF");
     }
 }
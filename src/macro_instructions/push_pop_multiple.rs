@@ -0,0 +1,66 @@
+use crate::{
+    instructions::{parse_reg_d, parse_reg_s},
+    macro_instructions::{ExpandFn, macro_instruction},
+    operand::op_values,
+};
+
+macro_instruction! {
+    name: "pushm",
+    operand_count: 1..,
+    expander: PUSHM,
+}
+
+macro_instruction! {
+    name: "popm",
+    operand_count: 1..,
+    expander: POPM,
+}
+
+const PUSHM: ExpandFn = |_, _, cond, ops| {
+    for op in ops {
+        parse_reg_s(op)?;
+    }
+
+    Ok(Some(
+        ops.iter()
+            .map(|&op| ("push", cond, op_values![op]))
+            .collect(),
+    ))
+};
+
+/// Pops in reverse, so that `popm` restores the registers of the `pushm` with the same list.
+const POPM: ExpandFn = |_, _, cond, ops| {
+    for op in ops {
+        parse_reg_d(op)?;
+    }
+
+    Ok(Some(
+        ops.iter()
+            .rev()
+            .map(|&op| ("pop", cond, op_values![op]))
+            .collect(),
+    ))
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::{macro_instructions::MACRO_INSTRUCTIONS, testkit::*};
+
+    #[test]
+    fn pushm_popm() {
+        let pushm = mc_instr("pushm");
+        let popm = mc_instr("popm");
+
+        assert_snapshot!(pushm("", &[]), @"Error: Macro-instruction 'pushm' requires at least 1 operands, got 0");
+        assert_snapshot!(pushm("", &["r1", "0x12"]), @"Error: Expected register, found immediate: 0x12");
+        assert_snapshot!(popm("", &["zero"]), @"Error: Register 'zero' is raed-only");
+
+        assert_snapshot!(pushm("", &["r1"]), @"push r1");
+        assert_snapshot!(pushm("", &["r1", "r2", "r3"]), @"push r1; push r2; push r3");
+        assert_snapshot!(popm("", &["r1", "r2", "r3"]), @"pop r3; pop r2; pop r1");
+        assert_snapshot!(popm("eq", &["r4", "r5"]), @"pop.eq r5; pop.eq r4");
+
+        assert_eq!(MACRO_INSTRUCTIONS["pushm"].imm_form(), None);
+        assert_eq!(MACRO_INSTRUCTIONS["popm"].imm_form(), None);
+    }
+}
@@ -1,36 +1,55 @@
 #![allow(clippy::unusual_byte_groupings)]
-#![feature(if_let_guard)]
 #![feature(decl_macro)]
-#![feature(new_range_api)]
 
+mod analysis;
 mod assembler;
 mod cli;
+mod emulator;
+mod formatter;
 mod instructions;
+mod lint;
+mod lsp;
 mod macro_instructions;
 mod operand;
 mod pass1;
 mod pass2;
 mod pseudo_instructions;
+mod signature;
+mod test_runner;
 #[cfg(test)]
 mod testkit;
 mod utils;
 
 use std::{
-    fs::read_to_string,
-    io::{BufWriter, Write, stdout},
+    fs::{read_dir, read_to_string, write},
+    io::{BufWriter, Write, stdin, stdout},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
 
 use crate::{
-    assembler::{Assembler, AssemblerSettings},
-    cli::{Cli, Output},
+    analysis::{Cfg, StackAnalysis},
+    assembler::{Assembler, AssemblerSettings, Program},
+    cli::{
+        AssembleArgs, CfgArgs, Cli, Command, FmtArgs, LintArgs, Output, RunArgs, StackArgs,
+        TestArgs,
+    },
+    emulator::{Emulator, EmulatorSettings, Profiler, Tracer},
+    formatter::format,
+    lint::{Level, LintConfig, lint},
+    lsp::Server,
+    test_runner::TestRunner,
     utils::align_tabbed_lines,
 };
 
-fn main() -> Result<()> {
+/// Exit status of `run` when the cycle limit is reached before the program halts.
+const EXIT_CYCLE_LIMIT: u8 = 2;
+
+fn main() -> Result<ExitCode> {
     env_logger::init();
 
     let cli = Cli::parse();
@@ -42,32 +61,47 @@ fn main() -> Result<()> {
             env!("CARGO_BIN_NAME"),
             &mut stdout(),
         );
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    match cli.command {
+        Some(Command::Run(args)) => return run(args),
+        Some(Command::Test(args)) => return test(args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Cfg(args)) => return cfg(args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Stack(args)) => return stack(args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Lint(args)) => return lint_files(args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Fmt(args)) => return fmt(args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Lsp) => return Server::new(stdout().lock()).run(stdin().lock()),
+        None => {}
     }
 
     let Some(src_file) = cli.src_file else {
         unreachable!()
     };
 
-    let source_lines = read_to_string(src_file)?
-        .lines()
-        .map(|s| s.to_string())
-        .collect();
+    let source_lines = read_source(&src_file)?;
 
     if matches!(cli.output, Output::Stdout) && cli.bin {
         bail!("Cannot write binary output to stdout.");
     }
 
-    let settings = AssemblerSettings {
-        disable_macro: cli.disable_macro,
-    };
+    let program = assemble(&cli.assemble, &source_lines)?;
 
-    let asmblr = Assembler::new(settings, source_lines);
-    let (codes, displays) = asmblr.assemble()?;
+    for warning in &program.warnings {
+        eprintln!("{}", warning);
+    }
+
+    if let Some(symbols) = cli.symbols {
+        symbols.get()?.write_all(program.symbols().as_bytes())?;
+    }
 
     let mut out = BufWriter::new(cli.output.get()?);
 
-    for (code, display) in codes.iter().zip(align_tabbed_lines(&displays)) {
+    for (code, display) in program
+        .codes
+        .iter()
+        .zip(align_tabbed_lines(&program.displays))
+    {
         if cli.bin {
             out.write_all(&code.to_be_bytes())?;
         } else {
@@ -75,5 +109,302 @@ fn main() -> Result<()> {
         }
     }
 
+    Ok(ExitCode::SUCCESS)
+}
+
+fn read_source(path: &str) -> Result<Vec<String>> {
+    Ok(read_to_string(path)?
+        .lines()
+        .map(|s| s.to_string())
+        .collect())
+}
+
+fn assemble(args: &AssembleArgs, source_lines: &[String]) -> Result<Program> {
+    let settings = AssemblerSettings {
+        disable_macro: args.disable_macro,
+        defines: args.define.clone(),
+    };
+
+    Assembler::new(settings, source_lines.to_vec()).assemble()
+}
+
+fn run(args: RunArgs) -> Result<ExitCode> {
+    let program = assemble(&args.assemble, &read_source(&args.src_file)?)?;
+
+    for warning in &program.warnings {
+        eprintln!("{}", warning);
+    }
+
+    let mut ranges = args.trace_range;
+    for label in &args.trace_label {
+        ranges.push(
+            program
+                .label_range(label)
+                .ok_or_else(|| anyhow!("Unknown label: '{}'", label))?,
+        );
+    }
+
+    let mut tracer = match args.trace {
+        Some(output) => Some(Tracer::new(
+            &program,
+            BufWriter::new(output.get()?),
+            args.trace_format,
+            ranges,
+        )),
+        None => None,
+    };
+
+    let mut profiler =
+        (args.profile.is_some() || args.profile_listing.is_some()).then(|| Profiler::new(&program));
+
+    let settings = EmulatorSettings {
+        inputs: args.input,
+        keys: args.keys,
+        seed: args.seed,
+        machine: args.machine,
+    };
+
+    let mut emu = Emulator::new(settings, &program.codes);
+
+    let result = loop {
+        if let Some(halt) = emu.halted() {
+            let pc = program.location(emu.pc());
+            eprintln!("halted: {} at {}", halt, pc);
+            break Ok(ExitCode::SUCCESS);
+        }
+
+        if args.max_cycles != 0 && emu.cycle() >= args.max_cycles {
+            eprintln!("stopped: reached the cycle limit");
+            break Ok(ExitCode::from(EXIT_CYCLE_LIMIT));
+        }
+
+        let pc = emu.pc();
+        match emu.step() {
+            Ok(step) => {
+                if let Some(tracer) = &mut tracer {
+                    tracer.record(&step)?;
+                }
+                if let Some(profiler) = &mut profiler {
+                    profiler.record(&step);
+                }
+            }
+            Err(e) => break Err(program.error_at(pc, e)),
+        }
+    };
+
+    if let Some(tracer) = &mut tracer {
+        tracer.flush()?;
+    }
+
+    if let Some(profiler) = &mut profiler {
+        profiler.finish();
+
+        if let Some(output) = &args.profile {
+            output.get()?.write_all(profiler.report().as_bytes())?;
+        }
+        if let Some(output) = &args.profile_listing {
+            output.get()?.write_all(profiler.annotate().as_bytes())?;
+        }
+    }
+
+    for value in &emu.outputs {
+        println!("{}", value);
+    }
+
+    eprint!("{}", emu.report());
+
+    result
+}
+
+fn test(args: TestArgs) -> Result<()> {
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_asm_files(Path::new(path), &mut files)?;
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    let mut out = stdout();
+
+    for file in files {
+        let source_lines = read_source(&file.to_string_lossy())?;
+
+        let result = assemble(&args.assemble, &source_lines).and_then(|program| {
+            let runner = TestRunner::new(&program, &source_lines)?
+                .with_max_cycles(args.max_cycles)
+                .with_machine(args.machine.clone());
+            if runner.is_empty() {
+                return Ok((0, 0));
+            }
+
+            writeln!(out, "running tests in {}", file.display())?;
+            let result = runner.run(&mut out)?;
+            writeln!(out)?;
+            Ok(result)
+        });
+
+        match result {
+            Ok((p, f)) => {
+                passed += p;
+                failed += f;
+            }
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "error in {}: {}\n", file.display(), e)?;
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "test result: {}. {} passed; {} failed",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed,
+        failed
+    )?;
+
+    if failed > 0 {
+        bail!("{} test(s) failed", failed);
+    }
+
+    Ok(())
+}
+
+fn cfg(args: CfgArgs) -> Result<()> {
+    let program = assemble(&args.assemble, &read_source(&args.src_file)?)?;
+    let cfg = Cfg::new(&program);
+
+    let functions = if args.function.is_empty() {
+        cfg.functions.iter().collect()
+    } else {
+        args.function
+            .iter()
+            .map(|name| {
+                cfg.function(name)
+                    .ok_or_else(|| anyhow!("Unknown function: '{}'", name))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut out = BufWriter::new(args.output.get()?);
+    for func in functions {
+        write!(out, "{}", cfg.to_dot(func))?;
+    }
+
+    Ok(())
+}
+
+fn stack(args: StackArgs) -> Result<()> {
+    let program = assemble(&args.assemble, &read_source(&args.src_file)?)?;
+    let cfg = Cfg::new(&program);
+
+    print!("{}", StackAnalysis::new(&program, &cfg).report());
+
+    Ok(())
+}
+
+fn lint_files(args: LintArgs) -> Result<()> {
+    let config = match &args.config {
+        Some(path) => LintConfig::parse(&read_to_string(path)?)?,
+        None => LintConfig::default(),
+    };
+
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_asm_files(Path::new(path), &mut files)?;
+    }
+
+    let (mut warnings, mut errors) = (0, 0);
+    let mut out = stdout();
+
+    for file in files {
+        let source_lines = read_source(&file.to_string_lossy())?;
+
+        let result = assemble(&args.assemble, &source_lines).and_then(|program| {
+            lint(
+                &program,
+                &source_lines,
+                &config,
+                args.assemble.disable_macro,
+            )
+        });
+
+        match result {
+            Ok(lints) => {
+                for lint in lints {
+                    match lint.level {
+                        Level::Deny => errors += 1,
+                        _ => warnings += 1,
+                    }
+                    writeln!(out, "{}: {}", file.display(), lint)?;
+                }
+            }
+            Err(e) => {
+                errors += 1;
+                writeln!(out, "error in {}: {}", file.display(), e)?;
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "lint result: {}. {} warning(s); {} error(s)",
+        if errors == 0 { "ok" } else { "FAILED" },
+        warnings,
+        errors
+    )?;
+
+    if errors > 0 {
+        bail!("{} lint error(s)", errors);
+    }
+
+    Ok(())
+}
+
+fn fmt(args: FmtArgs) -> Result<()> {
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_asm_files(Path::new(path), &mut files)?;
+    }
+
+    let mut unformatted = 0;
+    for file in files {
+        let source = read_to_string(&file)?;
+        let formatted = format(&source.lines().map(|s| s.to_string()).collect::<Vec<_>>());
+        if formatted == source {
+            continue;
+        }
+
+        if args.check {
+            println!("would reformat {}", file.display());
+            unformatted += 1;
+        } else {
+            write(&file, formatted)?;
+        }
+    }
+
+    if unformatted > 0 {
+        bail!("{} file(s) would be reformatted", unformatted);
+    }
+
+    Ok(())
+}
+
+fn collect_asm_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "asm") {
+            collect_asm_files(&entry, files)?;
+        }
+    }
+
     Ok(())
 }
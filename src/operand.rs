@@ -8,8 +8,8 @@ pub enum OperandValue<'a> {
     Unsigned(u32),
 }
 
-impl OperandValue<'_> {
-    pub fn as_str(&self) -> Option<&str> {
+impl<'a> OperandValue<'a> {
+    pub fn as_str(&self) -> Option<&'a str> {
         match self {
             Self::StringSlice(s) => Some(s),
             _ => None,
@@ -43,6 +43,8 @@ pub enum OperandType {
     RegD,
     RegS,
     Imm(ImmRange),
+    /// A 24-bit color, see [`crate::instructions::parse_color`].
+    Color,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1,21 +1,142 @@
-use std::collections::HashMap;
+mod layout;
+mod structured;
+
+use std::collections::{HashMap, HashSet};
 
 use anyhow::{Result, anyhow, bail};
 use bimap::BiHashMap;
 
-use crate::{macro_instructions::MACRO_INSTRUCTIONS, operand::OperandValue};
+use crate::{
+    instructions::{fmt_reg, parse_imm, parse_string},
+    macro_instructions::MACRO_INSTRUCTIONS,
+    operand::{OperandValue, op_values},
+    signature::Signature,
+};
+
+struct Constant<'a> {
+    value: OperandValue<'a>,
+    /// Index of the line of the last definition, `None` if defined on the command line.
+    line: Option<usize>,
+    /// Defined by `.set`, which may assign it again.
+    reassignable: bool,
+}
+
+/// An open `.if` block.
+struct Conditional {
+    /// Index of the line of the `.if`.
+    line: usize,
+    /// Whether the enclosing block is assembled.
+    outer: bool,
+    /// Whether one of the branches was assembled already.
+    taken: bool,
+    /// Whether the current branch is assembled.
+    active: bool,
+    in_else: bool,
+}
+
+/// An open `.rept` or `.irp` block.
+struct Repeat<'a> {
+    /// Index of the line of the directive.
+    line: usize,
+    /// The name bound to the counter of `.rept` or the values of `.irp`.
+    var: Option<&'a str>,
+    values: Vec<OperandValue<'a>>,
+    iteration: usize,
+    /// Numbers the current iteration, giving it its own local labels.
+    id: usize,
+    /// Constants defined in the body, for the current iteration only.
+    constants: HashMap<&'a str, Constant<'a>>,
+    /// The open `.if` blocks when the block started.
+    conditionals: usize,
+    /// The open structured blocks when the block started.
+    blocks: usize,
+}
+
+impl<'a> Repeat<'a> {
+    fn binding(&self) -> Option<(&'a str, Constant<'a>)> {
+        let constant = Constant {
+            value: self.values[self.iteration],
+            line: Some(self.line),
+            reassignable: false,
+        };
+        self.var.map(|var| (var, constant))
+    }
+}
+
+/// The `.func` last declared, whose body may be closed by `.endfunc`.
+struct Function<'a> {
+    name: &'a str,
+    /// Index of the line of the `.func`.
+    line: usize,
+    saves: Vec<OperandValue<'a>>,
+    /// Whether the label of the function was defined, from which `ret` restores the saves.
+    entered: bool,
+    /// The open structured blocks when the body started.
+    blocks: usize,
+}
 
 /// Pass 1
 ///
-/// 1. Record constants and labels.
-/// 2. Expand macro-instructions.
-/// 3. Substitute constants.
-/// 4. Build a mapping between new lines and the original lines.
+/// 0. Repeat the bodies of `.rept` and `.irp` blocks, and skip the branches of `.if`
+///    blocks whose condition doesn't hold.
+/// 1. Record the offsets of `.struct` fields and the values of `.enum` members.
+/// 2. Lower the structured control flow (`.if r1 lt r2`, `.while`, `.for`) to branches.
+/// 3. Record constants and labels.
+/// 4. Expand macro-instructions.
+/// 5. Substitute constants in mnemonics, conditions and operands, each as seen from its line.
+/// 6. Build a mapping between new lines and the original lines.
+/// 7. Record the `.func` signatures of labels, saving registers on entry and restoring
+///    them before each `ret` of the body.
+/// 8. Resolve local labels (`.loop`) and anonymous labels (`1f`, `1b`) to addresses, each
+///    iteration of a `.rept` or `.irp` block having its own local labels.
 pub struct Pass1<'a> {
     disable_macro: bool,
-    constants: HashMap<&'a str, &'a str>,
+    /// The value of each constant, the last one if it is defined in several scopes.
+    pub constants: HashMap<&'a str, &'a str>,
     pub labels: BiHashMap<&'a str, usize>,
+    /// Local labels by their name qualified with the global label and the iterations they
+    /// belong to, like `main.loop@3`.
+    local_labels: HashMap<String, usize>,
+    /// The name and address of each anonymous label, in order.
+    anonymous_labels: Vec<(&'a str, usize)>,
+    /// The global label the current line belongs to.
+    scope: Option<&'a str>,
+    /// The global label and the iterations of the open `.rept` blocks each address belongs to.
+    scopes: Vec<(Option<&'a str>, String)>,
+    /// Constants defined before the first label, visible everywhere.
+    global_constants: HashMap<&'a str, Constant<'a>>,
+    /// Constants defined under the current global label, shadowing the global ones.
+    local_constants: HashMap<&'a str, Constant<'a>>,
+    /// The index and content of the line defining each constant and label.
+    pub definitions: HashMap<&'a str, (usize, &'a str)>,
+    pub used_constants: HashSet<&'a str>,
+    /// The constants substituted on each line by line index, the last value for a line
+    /// repeated by `.rept`.
+    pub substitutions: HashMap<usize, HashMap<&'a str, OperandValue<'a>>>,
+    /// Index of the line being assembled.
+    line: usize,
+    pub signatures: HashMap<&'a str, Signature>,
+    function: Option<Function<'a>>,
+    conditionals: Vec<Conditional>,
+    /// The open `.rept` blocks, with the binding of their current iteration.
+    repeats: Vec<(Repeat<'a>, Option<(&'a str, Constant<'a>)>)>,
+    /// Iterations of `.rept` and `.irp` blocks started so far, numbering them.
+    iterations: usize,
+    /// The open `.struct` or `.enum` declaration.
+    layout: Option<layout::Layout<'a>>,
+    /// The line declaring each `.struct` and `.enum`.
+    layouts: HashMap<&'a str, usize>,
+    /// The fields of structs and the members of enums, by their qualified name `Cell.flags`.
+    members: HashMap<String, Constant<'a>>,
+    /// The open `.if`, `.while` and `.for` blocks of structured control flow.
+    blocks: Vec<structured::Block<'a>>,
+    /// Blocks opened and conditional `ret`s lowered so far, numbering their generated labels.
+    block_count: usize,
+    /// The labels generated for structured control flow, by address, for listings.
+    pub generated_labels: HashMap<usize, String>,
     pub addr_to_original: Vec<(usize, &'a str)>,
+    /// Whether each address comes from a macro expansion.
+    pub expanded: Vec<bool>,
     pub processed: Vec<(&'a str, Option<&'a str>, Vec<OperandValue<'a>>)>,
 }
 
@@ -25,15 +146,50 @@ impl<'a> Pass1<'a> {
             disable_macro,
             constants: HashMap::new(),
             labels: BiHashMap::new(),
+            local_labels: HashMap::new(),
+            anonymous_labels: Vec::new(),
+            scope: None,
+            scopes: Vec::new(),
+            global_constants: HashMap::new(),
+            local_constants: HashMap::new(),
+            definitions: HashMap::new(),
+            used_constants: HashSet::new(),
+            substitutions: HashMap::new(),
+            line: 0,
+            signatures: HashMap::new(),
+            function: None,
+            conditionals: Vec::new(),
+            repeats: Vec::new(),
+            iterations: 0,
+            layout: None,
+            layouts: HashMap::new(),
+            members: HashMap::new(),
+            blocks: Vec::new(),
+            block_count: 0,
+            generated_labels: HashMap::new(),
             addr_to_original: Vec::new(),
+            expanded: Vec::new(),
             processed: Vec::new(),
         }
     }
 
+    /// Defines a global constant before the first line, like `-D NAME=VALUE`.
+    pub fn define(&mut self, name: &'a str, value: &'a str) {
+        let constant = Constant {
+            value: value.into(),
+            line: None,
+            reassignable: false,
+        };
+        self.global_constants.insert(name, constant);
+    }
+
     pub fn run(&mut self, source_lines: &'a [String]) -> Result<()> {
-        let mut in_const_zone = true;
+        let mut next = 0;
+        while let Some(raw_line) = source_lines.get(next) {
+            let orig_idx = next;
+            next += 1;
+            self.line = orig_idx;
 
-        for (orig_idx, raw_line) in source_lines.iter().enumerate() {
             let raw_line = raw_line.trim();
             if raw_line.is_empty() {
                 continue;
@@ -44,38 +200,74 @@ impl<'a> Pass1<'a> {
                 continue;
             }
 
-            let tokens = raw_line.split_whitespace().collect::<Vec<_>>();
+            let tokens = tokenize(raw_line);
             if tokens.is_empty() {
                 unreachable!()
             }
 
-            if tokens[0] == "const" {
-                if in_const_zone {
-                    if tokens.len() != 3 {
-                        bail!("Malformed const at line {}: '{}'", orig_idx + 1, raw_line);
-                    }
-                    let [name, value, ..] = tokens[1..] else {
-                        unreachable!()
-                    };
-                    self.constants.insert(name, value);
-                    continue;
-                }
-
-                bail!(
-                    "Constants must be declared at the start of file (line {}): '{}'",
+            // Wraps the errors of the directive handlers with the line they come from.
+            let malformed = |e: anyhow::Error| {
+                anyhow!(
+                    "Malformed {} at line {}: '{}' ({})",
+                    tokens[0],
                     orig_idx + 1,
-                    raw_line
-                );
+                    raw_line,
+                    e
+                )
+            };
+
+            if self.conditional(&tokens, orig_idx).map_err(malformed)?
+                || self.conditionals.last().is_some_and(|c| !c.active)
+            {
+                continue;
+            }
+
+            if let Some(jump) = self
+                .repeat(&tokens, orig_idx, source_lines)
+                .map_err(malformed)?
+            {
+                next = jump;
+                continue;
+            }
+
+            if self.layout(&tokens, orig_idx).map_err(malformed)? {
+                continue;
             }
 
-            if in_const_zone {
-                in_const_zone = false;
+            if self
+                .structured(&tokens, orig_idx, raw_line)
+                .map_err(malformed)?
+            {
+                continue;
+            }
+
+            if tokens[0] == "const" || tokens[0] == ".set" {
+                if tokens.len() != 3 {
+                    bail!(
+                        "Malformed {} at line {}: '{}'",
+                        tokens[0],
+                        orig_idx + 1,
+                        raw_line
+                    );
+                }
+                self.define_constant(
+                    tokens[1],
+                    tokens[2],
+                    tokens[0] == ".set",
+                    orig_idx,
+                    raw_line,
+                )?;
+                continue;
+            }
+
+            if self.function(&tokens, orig_idx).map_err(malformed)? {
+                continue;
             }
 
             let (raw_line, tokens) = match tokens[0].strip_suffix(':') {
                 Some(label) => {
-                    let pc = self.processed.len();
-                    self.labels.insert(label, pc);
+                    self.define_label(label, orig_idx, raw_line)?;
+                    self.enter(label, orig_idx, raw_line)?;
 
                     if tokens.len() == 1 {
                         continue;
@@ -86,57 +278,1222 @@ impl<'a> Pass1<'a> {
                 None => (raw_line, tokens.as_ref()),
             };
 
-            let (name, cond) = if let Some((name, cond)) = tokens[0].split_once('.') {
-                (name, Some(cond))
-            } else {
-                (tokens[0], None)
+            let mnemonic = |pass1: &mut Self, token: &'a str| {
+                pass1.substitute(token).as_str().unwrap_or(token)
+            };
+            let (name, cond) = match mnemonic(self, tokens[0]).split_once('.') {
+                Some((name, cond)) => (mnemonic(self, name), Some(mnemonic(self, cond))),
+                None => (mnemonic(self, tokens[0]), None),
             };
 
+            // The operands of `pushm r1,r2` are a list.
+            let variadic = MACRO_INSTRUCTIONS
+                .get(name)
+                .is_some_and(|mc| mc.is_variadic());
             let ops = tokens[1..]
                 .iter()
-                .map(|e| {
-                    OperandValue::from(if let Some(&value) = self.constants.get(e) {
-                        value
-                    } else {
-                        e
-                    })
+                .flat_map(|&token| match variadic {
+                    true => split_words(token, |c| c == ',')
+                        .into_iter()
+                        .map(|(_, word)| word)
+                        .collect(),
+                    false => vec![token],
                 })
+                .map(|e| self.substitute(e))
                 .collect::<Vec<_>>();
 
-            let mut lines = Vec::new();
+            let saving = self
+                .function
+                .as_ref()
+                .is_some_and(|f| f.entered && !f.saves.is_empty());
+            match name {
+                "ret" if saving => self.ret(cond, ops, orig_idx, raw_line),
+                _ => self.push(name, cond, ops, orig_idx, raw_line),
+            }
+            .map_err(|e| {
+                anyhow!(
+                    "Error expanding macro-instruction at line {}: '{}' ({})",
+                    orig_idx + 1,
+                    raw_line,
+                    e
+                )
+            })?;
+        }
+
+        if let Some(conditional) = self.conditionals.last() {
+            bail!("Unterminated .if at line {}", conditional.line + 1);
+        }
+        if let Some((repeat, _)) = self.repeats.last() {
+            bail!("Unterminated .rept at line {}", repeat.line + 1);
+        }
+        if let Some(block) = self.blocks.last() {
+            bail!("Unterminated {} at line {}", block.kind, block.line + 1);
+        }
+        if let Some(layout) = &self.layout {
+            bail!("Unterminated {} at line {}", layout.kind, layout.line + 1);
+        }
+        if let Some(function) = self.function.take().filter(|f| !f.saves.is_empty()) {
+            bail!("Unterminated .func at line {}", function.line + 1);
+        }
 
-            if !self.disable_macro
-                && let Some(mc_instr) = MACRO_INSTRUCTIONS.get(name)
-                && let Some(expanded) = mc_instr.expand(cond, &ops).map_err(|e| {
-                    anyhow!(
-                        "Error expanding macro-instruction at line {}: '{}' ({})",
-                        orig_idx + 1,
-                        raw_line,
-                        e
-                    )
-                })?
+        let scopes = std::mem::take(&mut self.scopes);
+        for (addr, (scope, iterations)) in scopes.into_iter().enumerate() {
+            let (orig_idx, raw_line) = self.addr_to_original[addr];
+            for i in 0..self.processed[addr].2.len() {
+                let Some(name) = self.processed[addr].2[i].as_str() else {
+                    continue;
+                };
+                if let Some(target) = self.local_label(name, scope, &iterations, addr) {
+                    let target = target.ok_or_else(|| {
+                        anyhow!(
+                            "Unknown label at line {}: '{}' ({})",
+                            orig_idx + 1,
+                            raw_line,
+                            name
+                        )
+                    })?;
+                    self.processed[addr].2[i] = OperandValue::Unsigned(target as u32);
+                }
+            }
+        }
+
+        for (name, signature) in &self.signatures {
+            if self.labels.get_by_left(name).is_none() {
+                bail!(
+                    "Unknown label in .func at line {}: '{}'",
+                    signature.line + 1,
+                    name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends a line to the program, expanded if it is a macro-instruction.
+    fn push(
+        &mut self,
+        name: &'a str,
+        cond: Option<&'a str>,
+        ops: Vec<OperandValue<'a>>,
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<()> {
+        let mut lines = Vec::new();
+        let mut from_macro = false;
+
+        if !self.disable_macro
+            && let Some(mc_instr) = MACRO_INSTRUCTIONS.get(name)
+            && let Some(expanded) = mc_instr.expand(cond, &ops)?
+        {
+            lines.extend(expanded);
+            from_macro = true;
+        } else {
+            lines.push((name, cond, ops));
+        }
+
+        for line in lines {
+            self.scopes.push((self.scope, self.iteration_path()));
+            self.addr_to_original.push((orig_idx, raw_line.trim()));
+            self.expanded.push(from_macro);
+            self.processed.push(line);
+        }
+        Ok(())
+    }
+
+    /// Handles `.func` and `.endfunc`, returns whether `tokens` is one of them.
+    ///
+    /// A `.func` with saved registers starts a body that must be closed by `.endfunc`, the
+    /// saves are pushed at the label of the function and popped before each `ret`.
+    fn function(&mut self, tokens: &[&'a str], orig_idx: usize) -> Result<bool> {
+        match tokens[0] {
+            ".func" => {
+                if let Some(function) = self.function.as_ref().filter(|f| !f.saves.is_empty()) {
+                    bail!("unterminated .func at line {}", function.line + 1);
+                }
+                let Some(&name) = tokens.get(1) else {
+                    bail!("expected '.func NAME'");
+                };
+                let signature = Signature::parse(orig_idx, &tokens[2..], |reg| {
+                    self.resolve(reg).as_str().unwrap_or(reg)
+                })?;
+
+                self.function = Some(Function {
+                    name,
+                    line: orig_idx,
+                    saves: signature
+                        .saves
+                        .iter()
+                        .map(|&reg| OperandValue::from(fmt_reg(reg)))
+                        .collect(),
+                    entered: false,
+                    blocks: self.blocks.len(),
+                });
+                self.signatures.insert(name, signature);
+            }
+            ".endfunc" => {
+                if tokens.len() > 1 {
+                    bail!("unexpected operands");
+                }
+                let Some(function) = self.function.take() else {
+                    bail!("no matching .func");
+                };
+                if let Some(block) = self.blocks.get(function.blocks) {
+                    bail!("unterminated {} at line {}", block.kind, block.line + 1);
+                }
+                if !function.entered && !function.saves.is_empty() {
+                    bail!("no label '{}' after the .func", function.name);
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Saves the registers of the function labeled `label`, if it was just declared.
+    fn enter(&mut self, label: &'a str, orig_idx: usize, raw_line: &'a str) -> Result<()> {
+        let Some(function) = self
+            .function
+            .as_mut()
+            .filter(|f| f.name == label && !f.entered)
+        else {
+            return Ok(());
+        };
+        function.entered = true;
+
+        let saves = function.saves.clone();
+        if saves.is_empty() {
+            return Ok(());
+        }
+        self.push("pushm", None, saves, orig_idx, raw_line)
+    }
+
+    /// Restores the saved registers of the function and returns. A conditional `ret.eq`
+    /// jumps over both unless its condition holds, so that the pops are not predicated.
+    fn ret(
+        &mut self,
+        cond: Option<&'a str>,
+        ops: Vec<OperandValue<'a>>,
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<()> {
+        let saves = self
+            .function
+            .as_ref()
+            .map(|f| f.saves.clone())
+            .unwrap_or_default();
+
+        let skip = match cond {
+            Some(cond) => {
+                let Some(inverse) = structured::inverse(cond) else {
+                    bail!("Invalid condition: {}", cond);
+                };
+                self.push("jmp", Some(inverse), op_values![0], orig_idx, raw_line)?;
+                Some(self.processed.len() - 1)
+            }
+            None => None,
+        };
+        self.push("popm", None, saves, orig_idx, raw_line)?;
+        self.push("ret", None, ops, orig_idx, raw_line)?;
+
+        // Labeled like the blocks, so that a `.else` after it still jumps over its body.
+        if let Some(skip) = skip {
+            self.block_count += 1;
+            self.patch(skip, "ret", self.block_count);
+        }
+        Ok(())
+    }
+
+    /// Records a `const`, or a `.set` variable that may be assigned again, in the scope
+    /// of the current global label or globally before the first label. A constant defined
+    /// on the command line overrides the definitions of the source, which are defaults.
+    fn define_constant(
+        &mut self,
+        name: &'a str,
+        value: &'a str,
+        reassignable: bool,
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<()> {
+        if let Some(define) = self.global_constants.get(name)
+            && define.line.is_none()
+        {
+            if let Some(value) = define.value.as_str() {
+                self.constants.insert(name, value);
+            }
+            self.definitions.entry(name).or_insert((orig_idx, raw_line));
+            return Ok(());
+        }
+
+        let value = self.resolve(value);
+        let outer = match self.scope {
+            Some(_) => &mut self.local_constants,
+            None => &mut self.global_constants,
+        };
+        // The constants of a `.rept` body belong to its iteration, unless `.set` assigns a
+        // variable from outside the block.
+        let scope = match self.repeats.last_mut() {
+            Some((repeat, _))
+                if !(reassignable && outer.get(name).is_some_and(|c| c.reassignable)) =>
             {
-                lines.extend(expanded);
-            } else {
-                lines.push((name, cond, ops));
+                &mut repeat.constants
+            }
+            _ => outer,
+        };
+
+        if let Some(old) = scope.get(name)
+            && !(reassignable && old.reassignable)
+        {
+            bail!(
+                "Redefinition of {} at line {}: '{}' (previously defined at line {})",
+                if old.reassignable {
+                    "variable"
+                } else {
+                    "constant"
+                },
+                orig_idx + 1,
+                name,
+                old.line.unwrap_or_default() + 1
+            );
+        }
+        scope.insert(
+            name,
+            Constant {
+                value,
+                line: Some(orig_idx),
+                reassignable,
+            },
+        );
+
+        if let Some(value) = value.as_str() {
+            self.constants.insert(name, value);
+        }
+        self.definitions.entry(name).or_insert((orig_idx, raw_line));
+        Ok(())
+    }
+
+    /// Handles the `.if` family of directives, returns whether `tokens` is one.
+    ///
+    /// An `.if` comparing with a condition like `.if r1 lt r2` is left to structured control
+    /// flow, and so are the `.elif`, `.else` and `.endif` of its block.
+    fn conditional(&mut self, tokens: &[&str], orig_idx: usize) -> Result<bool> {
+        let active = self.conditionals.last().is_none_or(|c| c.active);
+
+        let structured = match tokens[0] {
+            ".if" => active && structured::is_test(&tokens[1..]),
+            ".elif" | ".else" | ".endif" => self
+                .blocks
+                .last()
+                .is_some_and(|b| b.conditionals == self.conditionals.len()),
+            _ => false,
+        };
+        if structured {
+            return Ok(false);
+        }
+
+        match tokens[0] {
+            ".if" | ".ifdef" | ".ifndef" => {
+                // Conditions inside skipped branches may refer to anything.
+                let holds = active && self.evaluate(tokens)?;
+                self.conditionals.push(Conditional {
+                    line: orig_idx,
+                    outer: active,
+                    taken: holds,
+                    active: holds,
+                    in_else: false,
+                });
             }
+            ".elif" | ".else" => {
+                let Some(c) = self.conditionals.last() else {
+                    bail!("no matching .if");
+                };
+                if c.in_else {
+                    bail!("{} after the .else at line {}", tokens[0], c.line + 1);
+                }
+                let (outer, taken) = (c.outer, c.taken);
 
-            for line in lines {
-                self.addr_to_original.push((orig_idx, raw_line.trim()));
-                self.processed.push(line);
+                let holds = match tokens[0] {
+                    ".else" if tokens.len() > 1 => bail!("unexpected operands"),
+                    ".else" => outer && !taken,
+                    _ => outer && !taken && self.evaluate(tokens)?,
+                };
+                let Some(c) = self.conditionals.last_mut() else {
+                    unreachable!()
+                };
+                c.taken |= holds;
+                c.active = holds;
+                c.in_else = tokens[0] == ".else";
+                if c.in_else {
+                    c.line = orig_idx;
+                }
             }
+            ".endif" => {
+                if tokens.len() > 1 {
+                    bail!("unexpected operands");
+                }
+                if self.conditionals.pop().is_none() {
+                    bail!("no matching .if");
+                }
+            }
+            _ => return Ok(false),
         }
 
+        Ok(true)
+    }
+
+    /// Handles `.rept`, `.irp` and `.endr`, returns the index of the next line to assemble
+    /// if `tokens` is one of them.
+    fn repeat(
+        &mut self,
+        tokens: &[&'a str],
+        orig_idx: usize,
+        source_lines: &[String],
+    ) -> Result<Option<usize>> {
+        let args = tokens[1..]
+            .iter()
+            .flat_map(|token| split_words(token, |c| c == ','))
+            .map(|(_, arg)| arg)
+            .collect::<Vec<_>>();
+
+        let (var, values) = match tokens[0] {
+            ".rept" => {
+                let [count, var @ ..] = args.as_slice() else {
+                    bail!("expected '.rept COUNT' or '.rept COUNT, NAME'");
+                };
+                if var.len() > 1 {
+                    bail!("expected '.rept COUNT' or '.rept COUNT, NAME'");
+                }
+                let count = parse_imm(&self.resolve(count))?;
+                let values = (0..count).map(OperandValue::Unsigned).collect::<Vec<_>>();
+                (var.first().copied(), values)
+            }
+            ".irp" => {
+                let [var, values @ ..] = args.as_slice() else {
+                    bail!("expected '.irp NAME, VALUE, ...'");
+                };
+                let mut expanded = Vec::new();
+                for value in values {
+                    // The characters of a string literal are iterated one by one.
+                    match self.resolve(value) {
+                        OperandValue::StringSlice(s) if s.starts_with('"') => expanded.extend(
+                            parse_string(s)?
+                                .into_iter()
+                                .map(|c| OperandValue::Unsigned(c as u32)),
+                        ),
+                        value => expanded.push(value),
+                    }
+                }
+                (Some(*var), expanded)
+            }
+            ".endr" => {
+                if !args.is_empty() {
+                    bail!("unexpected operands");
+                }
+                let Some((repeat, binding)) = self.repeats.last_mut() else {
+                    bail!("no matching .rept");
+                };
+                if self.conditionals.len() > repeat.conditionals {
+                    let line = self.conditionals.last().map_or(0, |c| c.line);
+                    bail!("unterminated .if at line {}", line + 1);
+                }
+                if let Some(block) = self.blocks.get(repeat.blocks) {
+                    bail!("unterminated {} at line {}", block.kind, block.line + 1);
+                }
+
+                repeat.iteration += 1;
+                if repeat.iteration < repeat.values.len() {
+                    self.iterations += 1;
+                    repeat.id = self.iterations;
+                    repeat.constants.clear();
+                    *binding = repeat.binding();
+                    return Ok(Some(repeat.line + 1));
+                }
+                self.repeats.pop();
+                return Ok(Some(orig_idx + 1));
+            }
+            _ => return Ok(None),
+        };
+
+        if values.is_empty() {
+            return end_of_repeat(source_lines, orig_idx).map(Some);
+        }
+
+        self.iterations += 1;
+        let repeat = Repeat {
+            line: orig_idx,
+            var,
+            values,
+            iteration: 0,
+            id: self.iterations,
+            constants: HashMap::new(),
+            conditionals: self.conditionals.len(),
+            blocks: self.blocks.len(),
+        };
+        let binding = repeat.binding();
+        self.repeats.push((repeat, binding));
+        Ok(Some(orig_idx + 1))
+    }
+
+    /// Evaluates the condition of `.if NAME`, `.if A OP B`, `.ifdef NAME` or `.ifndef NAME`,
+    /// where the operands are constants or immediates.
+    fn evaluate(&self, tokens: &[&str]) -> Result<bool> {
+        let value = |token: &str| -> Result<u32> {
+            parse_imm(&self.resolve(token))
+                .map_err(|_| anyhow!("expected a number, found '{}'", token))
+        };
+        // Numbers compare by value, anything else like registers by name.
+        let equal = |a: &str, b: &str| match (value(a), value(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => self.resolve(a) == self.resolve(b),
+        };
+
+        Ok(match tokens {
+            [".ifdef", name] => self.lookup(name).is_some(),
+            [".ifndef", name] => self.lookup(name).is_none(),
+            [".if" | ".elif", a] => value(a)? != 0,
+            [".if" | ".elif", a, "==", b] => equal(a, b),
+            [".if" | ".elif", a, "!=", b] => !equal(a, b),
+            [".if" | ".elif", a, op, b] => {
+                let (a, b) = (value(a)?, value(b)?);
+                match *op {
+                    "<" => a < b,
+                    "<=" => a <= b,
+                    ">" => a > b,
+                    ">=" => a >= b,
+                    _ => bail!("unknown operator '{}'", op),
+                }
+            }
+            _ => bail!("expected 'NAME' or 'A OP B'"),
+        })
+    }
+
+    /// Substitutes `token` if it names a constant, without counting it as used.
+    fn resolve<'b>(&self, token: &'b str) -> OperandValue<'b>
+    where
+        'a: 'b,
+    {
+        if let Some(value) = self.negation(token) {
+            return value;
+        }
+        self.lookup(token)
+            .map_or(token.into(), |constant| constant.value)
+    }
+
+    /// The value of `-NAME` for a numeric constant `NAME`, in two's complement.
+    fn negation(&self, token: &str) -> Option<OperandValue<'static>> {
+        let constant = self.lookup(token.strip_prefix('-')?)?;
+        let value = parse_imm(&constant.value).ok()?;
+        Some(OperandValue::Unsigned(value.wrapping_neg()))
+    }
+
+    /// The constant `name` as seen from the current line.
+    fn lookup(&self, name: &str) -> Option<&Constant<'a>> {
+        self.repeats
+            .iter()
+            .rev()
+            .find_map(|(repeat, binding)| {
+                repeat.constants.get(name).or(match binding {
+                    Some((var, constant)) if *var == name => Some(constant),
+                    _ => None,
+                })
+            })
+            .or_else(|| self.local_constants.get(name))
+            .or_else(|| self.global_constants.get(name))
+            .or_else(|| self.members.get(name))
+    }
+
+    /// Substitutes `token` if it names a constant, or is `-NAME`.
+    fn substitute(&mut self, token: &'a str) -> OperandValue<'a> {
+        let value = if let Some(value) = self.negation(token) {
+            self.used_constants.insert(&token[1..]);
+            value
+        } else if let Some(constant) = self.lookup(token) {
+            let value = constant.value;
+            self.used_constants.insert(token);
+            value
+        } else {
+            return token.into();
+        };
+
+        self.substitutions
+            .entry(self.line)
+            .or_default()
+            .insert(token, value);
+        value
+    }
+
+    /// The values of the constants defined before the first label.
+    pub fn global_values(&self) -> impl Iterator<Item = (&'a str, OperandValue<'a>)> + '_ {
+        self.global_constants
+            .iter()
+            .map(|(&name, constant)| (name, constant.value))
+    }
+
+    /// Records a global, local (`.name`) or anonymous (`1`) label at the next address.
+    fn define_label(&mut self, label: &'a str, orig_idx: usize, raw_line: &'a str) -> Result<()> {
+        let pc = self.processed.len();
+
+        if is_anonymous(label) {
+            self.anonymous_labels.push((label, pc));
+            return Ok(());
+        }
+
+        if label.starts_with('.') {
+            let Some(scope) = self.scope else {
+                bail!(
+                    "Local label before any global label at line {}: '{}'",
+                    orig_idx + 1,
+                    raw_line
+                );
+            };
+            let key = format!("{}{}{}", scope, label, self.iteration_path());
+            if self.local_labels.insert(key, pc).is_some() {
+                bail!(
+                    "Duplicate label at line {}: '{}' (already defined in '{}')",
+                    orig_idx + 1,
+                    label,
+                    scope
+                );
+            }
+            return Ok(());
+        }
+
+        if self.labels.contains_left(label) {
+            bail!(
+                "Duplicate label at line {}: '{}' (first defined at line {})",
+                orig_idx + 1,
+                label,
+                self.definitions[label].0 + 1
+            );
+        }
+        self.labels.insert(label, pc);
+        self.definitions.insert(label, (orig_idx, raw_line));
+        self.scope = Some(label);
+        self.local_constants.clear();
+
         Ok(())
     }
+
+    /// The iterations of the open `.rept` blocks, like `@1@3`, qualifying local labels.
+    fn iteration_path(&self) -> String {
+        self.repeats
+            .iter()
+            .map(|(repeat, _)| format!("@{}", repeat.id))
+            .collect()
+    }
+
+    /// The address of a local label referenced from `scope` within `iterations`, the
+    /// innermost iteration first, or of an anonymous label referenced from `addr`, `1f`
+    /// being the next `1:` and `1b` the previous one.
+    ///
+    /// Returns `None` if `name` is neither, and `Some(None)` if there is no such label.
+    fn local_label(
+        &self,
+        name: &str,
+        scope: Option<&str>,
+        iterations: &str,
+        addr: usize,
+    ) -> Option<Option<usize>> {
+        if name.starts_with('.') {
+            let Some(scope) = scope else {
+                return Some(None);
+            };
+            let mut iterations = iterations;
+            return Some(loop {
+                let key = format!("{}{}{}", scope, name, iterations);
+                if let Some(&target) = self.local_labels.get(&key) {
+                    break Some(target);
+                }
+                match iterations.rsplit_once('@') {
+                    Some((outer, _)) => iterations = outer,
+                    None => break None,
+                }
+            });
+        }
+
+        let (label, forward) = match name.split_at_checked(name.len().checked_sub(1)?)? {
+            (label, "f") if is_anonymous(label) => (label, true),
+            (label, "b") if is_anonymous(label) => (label, false),
+            _ => return None,
+        };
+        let mut candidates = self.anonymous_labels.iter().filter(|&&(l, _)| l == label);
+        Some(if forward {
+            candidates
+                .find(|&&(_, target)| target > addr)
+                .map(|&(_, target)| target)
+        } else {
+            candidates
+                .rfind(|&&(_, target)| target <= addr)
+                .map(|&(_, target)| target)
+        })
+    }
+}
+
+/// The index of the line after the `.endr` closing the block opened at `start`.
+fn end_of_repeat(source_lines: &[String], start: usize) -> Result<usize> {
+    let mut depth = 0;
+    for (idx, line) in source_lines.iter().enumerate().skip(start + 1) {
+        match tokenize(strip_comment(line)).first().copied() {
+            Some(".rept" | ".irp") => depth += 1,
+            Some(".endr") if depth == 0 => return Ok(idx + 1),
+            Some(".endr") => depth -= 1,
+            _ => {}
+        }
+    }
+
+    bail!("no matching .endr")
+}
+
+fn is_anonymous(label: &str) -> bool {
+    !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit())
 }
 
 fn strip_comment(s: &str) -> &str {
-    if let Some(idx) = s.find(';') {
-        &s[..idx]
-    } else if let Some(idx) = s.find('#') {
-        &s[..idx]
-    } else {
-        s
+    split_comment(s).0
+}
+
+/// Splits a line into its code and its comment, with the marker `;` or `#` that starts it.
+/// Markers inside character and string literals like `';'` do not start a comment, nor
+/// does the `#` of a color operand like `#FF0000`.
+pub fn split_comment(s: &str) -> (&str, Option<(char, &str)>) {
+    let unquoted = || {
+        unquoted(s)
+            .filter(|&(.., quoted)| !quoted)
+            .map(|(idx, c, _)| (idx, c))
+    };
+    let idx = unquoted()
+        .find(|&(_, c)| c == ';')
+        .or_else(|| unquoted().find(|&(idx, c)| c == '#' && !is_color(s, idx)));
+    match idx {
+        Some((idx, marker)) => (&s[..idx], Some((marker, &s[idx + 1..]))),
+        None => (s, None),
+    }
+}
+
+/// Whether the `#` at `idx` starts a color operand: it follows code and is followed by
+/// exactly six hexadecimal digits.
+fn is_color(s: &str, idx: usize) -> bool {
+    let before = &s[..idx];
+    let digits = s[idx + 1..]
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(s.len() - idx - 1);
+    let after = s[idx + 1 + digits..].chars().next();
+
+    !before.trim().is_empty()
+        && before.ends_with(char::is_whitespace)
+        && digits == 6
+        && after.is_none_or(|c| c.is_whitespace() || c == ',' || c == ';')
+}
+
+/// Splits code into tokens on whitespace, a literal like `' '` or a group like
+/// `rgb(0, 0, 0)` stays one token.
+pub fn tokenize(code: &str) -> Vec<&str> {
+    split_words(code, char::is_whitespace)
+        .into_iter()
+        .map(|(_, word)| word)
+        .collect()
+}
+
+/// Splits `code` on the characters matching `separator` outside of literals, dropping
+/// empty words, with the byte offset of each word.
+pub fn split_words(code: &str, separator: impl Fn(char) -> bool) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (idx, c, quoted) in unquoted(code) {
+        match (start, !quoted && separator(c)) {
+            (None, false) => start = Some(idx),
+            (Some(s), true) => {
+                words.push((s, &code[s..idx]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, &code[s..]));
+    }
+    words
+}
+
+/// The characters of `s` with whether they are part of a character or string literal, or
+/// of a parenthesized group.
+fn unquoted(s: &str) -> impl Iterator<Item = (usize, char, bool)> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0usize;
+    s.char_indices().map(move |(idx, c)| {
+        let quoted = quote.is_some() || depth > 0;
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '(' => depth += 1,
+            None if c == ')' => depth = depth.saturating_sub(1),
+            None => {}
+        }
+        (idx, c, quoted || quote.is_some() || depth > 0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testkit::*, utils::fmt_line};
+
+    fn pass1(src: &str) -> String {
+        pass1_with(src, &[])
+    }
+
+    fn pass1_with(src: &str, defines: &[(&str, &str)]) -> String {
+        let source_lines = src.lines().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut pass1 = Pass1::new(false);
+        for &(name, value) in defines {
+            pass1.define(name, value);
+        }
+        match pass1.run(&source_lines) {
+            Ok(()) => pass1
+                .processed
+                .into_iter()
+                .map(|(name, cond, ops)| fmt_line(name, cond, ops))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+
+    #[test]
+    fn labels() {
+        assert_snapshot!(pass1("
+            main:
+            .loop:
+                dec r1
+                bne r1 zero .loop
+                jmp 1f
+            1:  jmp 1b
+            1:  call work
+            work:
+            .loop:
+                jmp .loop
+            "), @r"
+        dec r1
+        bne r1 zero 0
+        jmp 3
+        jmp 3
+        call work
+        jmp 5
+        ");
+
+        assert_snapshot!(pass1("
+            main:
+                jmp main
+            main:
+                ret
+            "), @"Error: Duplicate label at line 4: 'main' (first defined at line 2)");
+        assert_snapshot!(pass1("
+            main:
+            .loop:
+            .loop:
+                ret
+            "), @"Error: Duplicate label at line 4: '.loop' (already defined in 'main')");
+        assert_snapshot!(pass1("
+            main:
+                jmp .loop
+            work:
+            .loop:
+                ret
+            "), @"Error: Unknown label at line 3: 'jmp .loop' (.loop)");
+        assert_snapshot!(pass1("1: jmp 1f"), @"Error: Unknown label at line 1: 'jmp 1f' (1f)");
+        assert_snapshot!(pass1(".loop: ret"), @"Error: Local label before any global label at line 1: '.loop: ret'");
+    }
+
+    #[test]
+    fn constants() {
+        assert_snapshot!(pass1("
+            const x r1
+            const jump jmp
+            const cond lt
+            main:
+                const x r2
+                const y x
+                li x 1
+                mv y r3
+                jump.cond main
+                .set n 1
+                addi x x n
+                .set n 2
+                addi x x n
+            work:
+                li x 3
+            "), @r"
+        li r2 1
+        mv r2 r3
+        jmp.lt main
+        addi r2 r2 1
+        addi r2 r2 2
+        li r1 3
+        ");
+
+        assert_snapshot!(pass1("
+            const x r1
+            const x r2
+            "), @"Error: Redefinition of constant at line 3: 'x' (previously defined at line 2)");
+        assert_snapshot!(pass1("
+            main:
+                .set n 1
+                const n 2
+            "), @"Error: Redefinition of variable at line 4: 'n' (previously defined at line 3)");
+        assert_snapshot!(pass1("
+            main:
+                const n 1
+                .set n 2
+            "), @"Error: Redefinition of constant at line 4: 'n' (previously defined at line 3)");
+    }
+
+    #[test]
+    fn conditionals() {
+        let src = "
+            .ifndef LIVES
+            const LIVES 3
+            .endif
+            main:
+            .ifdef CHEAT
+                li r1 99
+            .elif LIVES > 5
+                li r1 5
+            .else
+                li r1 LIVES
+                .if LIVES == 3
+                    inc r1
+                .endif
+            .endif
+            ";
+        assert_snapshot!(pass1(src), @r"
+        li r1 3
+        inc r1
+        ");
+        assert_snapshot!(pass1_with(src, &[("CHEAT", "1"), ("LIVES", "9")]), @"li r1 99");
+        assert_snapshot!(pass1_with(src, &[("LIVES", "9")]), @"li r1 5");
+        assert_snapshot!(pass1_with(src, &[("LIVES", "r1")]), @"Error: Malformed .elif at line 8: '.elif LIVES > 5' (expected a number, found 'LIVES')");
+
+        assert_snapshot!(pass1(".if 1\n.else\n.else\n.endif"), @"Error: Malformed .else at line 3: '.else' (.else after the .else at line 2)");
+        assert_snapshot!(pass1(".endif"), @"Error: Malformed .endif at line 1: '.endif' (no matching .if)");
+        assert_snapshot!(pass1(".if 1 ~ 2"), @"Error: Malformed .if at line 1: '.if 1 ~ 2' (unknown operator '~')");
+        assert_snapshot!(pass1("main:\n.if 1\nret"), @"Error: Unterminated .if at line 2");
+        assert_snapshot!(pass1("const X 1\nmain:\nconst Y 3\nli r1 X\nli r2 Y"), @r"
+        li r1 1
+        li r2 3
+        ");
+        assert_snapshot!(pass1_with("const X 1\nmain:\nconst Y 3\nli r1 X\nli r2 Y", &[("X", "2"), ("Y", "4")]), @r"
+        li r1 2
+        li r2 4
+        ");
+    }
+
+    #[test]
+    fn repeats() {
+        assert_snapshot!(pass1("
+            const x r1
+            main:
+            .rept 2
+                inc r1
+            .endr
+            .rept 3, i
+                .if i != 1
+                    li r2 i
+                .endif
+                .irp reg, x, r3
+                    add reg reg i
+                .endr
+            .endr
+            .rept 0
+                jmp main
+                .rept 2
+                .endr
+            .endr
+            .irp n
+            .endr
+            "), @r"
+        inc r1
+        inc r1
+        li r2 0
+        addi r1 r1 0
+        addi r3 r3 0
+        addi r1 r1 1
+        addi r3 r3 1
+        li r2 2
+        addi r1 r1 2
+        addi r3 r3 2
+        ");
+
+        assert_snapshot!(pass1("main:\n.rept 3\n1: inc r1\nbne r1 zero 1b\n.endr"), @r"
+        inc r1
+        bne r1 zero 0
+        inc r1
+        bne r1 zero 2
+        inc r1
+        bne r1 zero 4
+        ");
+        assert_snapshot!(pass1("main:\n.rept 2\n.loop: dec r1\nbne r1 zero .loop\njmp .done\n.endr\n.done: ret"), @r"
+        dec r1
+        bne r1 zero 0
+        jmp 6
+        dec r1
+        bne r1 zero 3
+        jmp 6
+        ret
+        ");
+
+        assert_snapshot!(pass1("main:\n.set n 0\n.rept 3, i\nconst Y i\n.set n Y\nli r1 Y\n.endr\nli r2 n"), @r"
+        li r1 0
+        li r1 1
+        li r1 2
+        li r2 2
+        ");
+        assert_snapshot!(pass1("main:\n.rept 2\nconst Y 1\nconst Y 2\n.endr"), @"Error: Redefinition of constant at line 4: 'Y' (previously defined at line 3)");
+
+        assert_snapshot!(pass1(".rept 2\n.if 1\n.endr"), @"Error: Malformed .endr at line 3: '.endr' (unterminated .if at line 2)");
+        assert_snapshot!(pass1(".endr"), @"Error: Malformed .endr at line 1: '.endr' (no matching .rept)");
+        assert_snapshot!(pass1(".rept 1\nret"), @"Error: Unterminated .rept at line 1");
+        assert_snapshot!(pass1(".rept 0\nret"), @"Error: Malformed .rept at line 1: '.rept 0' (no matching .endr)");
+    }
+
+    #[test]
+    fn literals() {
+        assert_snapshot!(pass1(r#"
+            const SPACE ' '
+            main:
+                li r1 ';' ; a comment
+                li r2 SPACE # another
+                .irp c, "a,b", '#'
+                    li r3 c
+                .endr
+            "#), @r"
+        li r1 ';'
+        li r2 ' '
+        li r3 97
+        li r3 44
+        li r3 98
+        li r3 '#'
+        ");
+
+        assert_snapshot!(pass1(".irp c, \"\\q\"\n.endr"), @r#"Error: Malformed .irp at line 1: '.irp c, "\q"' (Invalid string literal: "\q")"#);
+    }
+
+    #[test]
+    fn negatives() {
+        assert_snapshot!(pass1("
+            const STEP 4
+            const DOWN -STEP
+            main:
+                li r1 -5
+                addi r1 r1 -STEP
+                subi r1 r1 DOWN
+                li r2 -0x1_0000
+            "), @r"
+        subi r1 zero 5
+        subi r1 r1 4
+        addi r1 r1 4
+        lui r2 0xFFFF0
+        ori r2 r2 0
+        ");
+    }
+
+    #[test]
+    fn colors() {
+        assert_snapshot!(pass1("
+            const SNAKE #00FF00 # the head
+            main:
+                col #FF0000 ; red
+                col rgb(0, 128, 255) # a comment
+                col SNAKE
+                col orange
+            "), @r"
+        col #FF0000
+        col rgb(0, 128, 255)
+        col #00FF00
+        col orange
+        ");
+    }
+
+    #[test]
+    fn structured() {
+        assert_snapshot!(pass1("
+            const i r2
+            main:
+                .for i = 1 to 10
+                    .if i eq 3
+                        .continue
+                    .elif r1 ge i
+                        add r1 r1 i
+                    .else
+                        .ifdef DEBUG
+                            mv io i
+                        .endif
+                    .endif
+                .endfor
+                .while r1 ne 0
+                    .rept 2
+                        .while
+                            .break r1 lt r3
+                            dec r1
+                        .endwhile
+                    .endr
+                    .break.eq
+                .endwhile
+            "), @r"
+        li r2 1
+        li tmp 10
+        bgt r2 tmp 11
+        li tmp 3
+        bne r2 tmp 6
+        jmp 9
+        blt r1 r2 9
+        add r1 r1 r2
+        jmp 9
+        inc r2
+        jmp 1
+        beq r1 zero 20
+        blt r1 r3 15
+        dec r1
+        jmp 12
+        blt r1 r3 18
+        dec r1
+        jmp 15
+        jmp.eq 20
+        jmp 11
+        ");
+
+        assert_snapshot!(pass1(".func f saves r4\nf:\n.if r1 lt r2\nret.ne\n.elif r1 eq r3\nret.eq\n.else\nli r3 99\n.endif\nret\n.endfunc"), @r"
+        push r4
+        bge r1 r2 6
+        jmp.eq 5
+        pop r4
+        ret
+        jmp 12
+        bne r1 r3 11
+        jmp.ne 10
+        pop r4
+        ret
+        jmp 12
+        li r3 99
+        pop r4
+        ret
+        ");
+
+        assert_snapshot!(pass1(".while r1 lt r2\n.endif"), @"Error: Malformed .endif at line 2: '.endif' (expected .endwhile for the .while at line 1)");
+        assert_snapshot!(pass1(".if r1 lt r2\n.endwhile"), @"Error: Malformed .endwhile at line 2: '.endwhile' (expected .endif for the .if at line 1)");
+        assert_snapshot!(pass1(".while\n.ifdef X\n.endwhile"), @"Error: Unterminated .if at line 2");
+        assert_snapshot!(pass1(".if r1 lt r2\n.else\n.else"), @"Error: Malformed .else at line 3: '.else' (.else after the .else of the .if at line 1)");
+        assert_snapshot!(pass1(".if r1 xx r2\n.endif"), @"Error: Malformed .if at line 1: '.if r1 xx r2' (expected a number, found 'r1')");
+        assert_snapshot!(pass1(".for r1 = 0 until 3"), @"Error: Malformed .for at line 1: '.for r1 = 0 until 3' (expected '.for REG = FROM to TO')");
+        assert_snapshot!(pass1(".if r1 lt r2\n.break"), @"Error: Malformed .break at line 2: '.break' (not inside a .while or .for)");
+        assert_snapshot!(pass1(".for r1 = 0 to 3"), @"Error: Unterminated .for at line 1");
+        assert_snapshot!(pass1(".rept 2\n.while\n.endr"), @"Error: Malformed .endr at line 3: '.endr' (unterminated .while at line 2)");
+    }
+
+    #[test]
+    fn functions() {
+        assert_snapshot!(pass1("
+            const s0 r5
+            main:
+              call f
+              pushm r1, r2,r3
+              popm r1,r2 r3
+            .func f in r1 saves r4,s0
+            f:
+              cmp r1 zero
+              ret.eq
+              .while r1 gt zero
+                dec r1
+              .endwhile
+              ret
+            .endfunc
+            g:
+              ret
+        "), @r"
+        call f
+        push r1
+        push r2
+        push r3
+        pop r3
+        pop r2
+        pop r1
+        push r4
+        push r5
+        cmp r1 zero
+        jmp.ne 14
+        pop r5
+        pop r4
+        ret
+        ble r1 zero 17
+        dec r1
+        jmp 14
+        pop r5
+        pop r4
+        ret
+        ret
+        ");
+
+        assert_snapshot!(pass1(".func f saves r4\nf:\n.func g\ng:"), @"Error: Malformed .func at line 3: '.func g' (unterminated .func at line 1)");
+        assert_snapshot!(pass1(".func f saves r4\nf:\nret"), @"Error: Unterminated .func at line 1");
+        assert_snapshot!(pass1(".func f saves r4\n.endfunc\nf:"), @"Error: Malformed .endfunc at line 2: '.endfunc' (no label 'f' after the .func)");
+        assert_snapshot!(pass1(".func f\nf:\n.while\n.endfunc"), @"Error: Malformed .endfunc at line 4: '.endfunc' (unterminated .while at line 3)");
+        assert_snapshot!(pass1(".endfunc"), @"Error: Malformed .endfunc at line 1: '.endfunc' (no matching .func)");
+        assert_snapshot!(pass1("pushm"), @"Error: Error expanding macro-instruction at line 1: 'pushm' (Macro-instruction 'pushm' requires at least 1 operands, got 0)");
+    }
+
+    #[test]
+    fn layouts() {
+        assert_snapshot!(pass1("
+            const ROWS 3
+            .struct Point
+              x
+              y
+            .endstruct
+            .struct Cell
+              flags
+              pos Point.size
+              rows ROWS
+            .endstruct
+            .enum Dir
+              UP
+              DOWN
+              LEFT = 8
+              RIGHT
+            .endenum
+            main:
+              lw r1 r2 Cell.flags
+              addi r3 r2 Cell.pos
+              lw r4 r3 Point.y
+              li r5 Cell.size
+              li r6 -Cell.size
+              li r7 Dir.RIGHT
+              beqi r7 Dir.DOWN main
+        "), @r"
+        lw r1 r2 0
+        addi r3 r2 1
+        lw r4 r3 1
+        li r5 6
+        subi r6 zero 6
+        li r7 9
+        li tmp 1
+        beq r7 tmp main
+        ");
+
+        assert_snapshot!(pass1(".struct A\nx\n.endenum"), @"Error: Malformed .endenum at line 3: '.endenum' (expected .endstruct for the .struct at line 1)");
+        assert_snapshot!(pass1(".struct A\nx\nx\n.endstruct"), @"Error: Malformed x at line 3: 'x' ('A.x' is already defined at line 2)");
+        assert_snapshot!(pass1(".struct A\nsize\n.endstruct"), @"Error: Malformed .endstruct at line 3: '.endstruct' ('A.size' is already defined at line 2)");
+        assert_snapshot!(pass1(".enum A\n.endenum\n.struct A"), @"Error: Malformed .struct at line 3: '.struct A' ('A' is already declared at line 1)");
+        assert_snapshot!(pass1(".enum A\nB = x"), @"Error: Malformed B at line 2: 'B = x' (Invalid immediate: x)");
+        assert_snapshot!(pass1(".enum A\nB 1"), @"Error: Malformed B at line 2: 'B 1' (expected a member like 'MEMBER [= VALUE]')");
+        assert_snapshot!(pass1(".enum A\nB = -1\n.endenum\nmain:\nli r1 A.B"), @"subi r1 zero 1");
+        assert_snapshot!(pass1(".enum A\nB = -1\nC\n.endenum"), @"Error: Malformed C at line 3: 'C' (the member 'C' overflows)");
+        assert_snapshot!(pass1(".struct A\nx 0xFFFFFFFF\ny\n.endstruct"), @"Error: Malformed .endstruct at line 4: '.endstruct' (the size of 'A' overflows)");
+        assert_snapshot!(pass1(".struct A\n.struct B"), @"Error: Malformed .struct at line 2: '.struct B' (unterminated .struct at line 1)");
+        assert_snapshot!(pass1(".endstruct"), @"Error: Malformed .endstruct at line 1: '.endstruct' (no matching .struct)");
+        assert_snapshot!(pass1(".enum A\nB"), @"Error: Unterminated .enum at line 1");
     }
 }
@@ -0,0 +1,173 @@
+use std::fmt::Display;
+
+use anyhow::{Result, bail};
+
+use crate::{
+    instructions::parse_imm,
+    operand::OperandValue,
+    pass1::{Constant, Pass1},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Struct,
+    Enum,
+}
+
+impl Kind {
+    fn end(self) -> &'static str {
+        match self {
+            Kind::Struct => ".endstruct",
+            Kind::Enum => ".endenum",
+        }
+    }
+
+    fn member(self) -> &'static str {
+        match self {
+            Kind::Struct => "field",
+            Kind::Enum => "member",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Struct => write!(f, ".struct"),
+            Kind::Enum => write!(f, ".enum"),
+        }
+    }
+}
+
+/// An open `.struct` or `.enum` declaration.
+pub struct Layout<'a> {
+    pub kind: Kind,
+    /// Index of the line of the directive.
+    pub line: usize,
+    name: &'a str,
+    /// The offset of the next field, or the value of the next member, `None` if it overflows.
+    next: Option<u32>,
+}
+
+impl<'a> Pass1<'a> {
+    /// Handles the declarations of memory layouts, returns whether `tokens` is one of them
+    /// or a line of their body.
+    ///
+    /// Each line of a `.struct NAME` declares a field `FIELD [SIZE]` of `SIZE` words, one by
+    /// default, defining the constant `NAME.FIELD` to its offset, and `.endstruct` defines
+    /// `NAME.size` to the total size. Each line of an `.enum NAME` declares a member
+    /// `MEMBER [= VALUE]`, defining `NAME.MEMBER` to the value of the previous member plus
+    /// one, starting from 0. The constants are global.
+    pub(super) fn layout(&mut self, tokens: &[&'a str], orig_idx: usize) -> Result<bool> {
+        let kind = match tokens[0] {
+            ".struct" => Some(Kind::Struct),
+            ".enum" => Some(Kind::Enum),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            if let Some(layout) = &self.layout {
+                bail!("unterminated {} at line {}", layout.kind, layout.line + 1);
+            }
+            let &[_, name] = tokens else {
+                bail!("expected '{} NAME'", kind);
+            };
+            if let Some(line) = self.layouts.get(name) {
+                bail!("'{}' is already declared at line {}", name, line + 1);
+            }
+
+            self.layouts.insert(name, orig_idx);
+            self.layout = Some(Layout {
+                kind,
+                line: orig_idx,
+                name,
+                next: Some(0),
+            });
+            return Ok(true);
+        }
+
+        let Some(layout) = &self.layout else {
+            return match tokens[0] {
+                ".endstruct" | ".endenum" => bail!("no matching .{}", &tokens[0][4..]),
+                _ => Ok(false),
+            };
+        };
+        let (kind, name, next) = (layout.kind, layout.name, layout.next);
+
+        if tokens[0] == ".endstruct" || tokens[0] == ".endenum" {
+            if tokens[0] != kind.end() {
+                bail!(
+                    "expected {} for the {} at line {}",
+                    kind.end(),
+                    kind,
+                    layout.line + 1
+                );
+            }
+            if tokens.len() > 1 {
+                bail!("unexpected operands");
+            }
+            if kind == Kind::Struct {
+                let Some(size) = next else {
+                    bail!("the size of '{}' overflows", name);
+                };
+                self.define_member(name, "size", size, orig_idx)?;
+            }
+            self.layout = None;
+            return Ok(true);
+        }
+
+        let (member, value, next) = match (kind, tokens) {
+            (Kind::Struct, &[field]) => (field, next, next.and_then(|n| n.checked_add(1))),
+            (Kind::Struct, &[field, size]) => {
+                let size = parse_imm(&self.substitute(size))?;
+                (field, next, next.and_then(|n| n.checked_add(size)))
+            }
+            (Kind::Enum, &[member]) => (member, next, next.and_then(|n| n.checked_add(1))),
+            (Kind::Enum, &[member, "=", value]) => {
+                let value = parse_imm(&self.substitute(value))?;
+                (member, Some(value), value.checked_add(1))
+            }
+            (Kind::Struct, _) => bail!("expected a field like 'FIELD [SIZE]'"),
+            (Kind::Enum, _) => bail!("expected a member like 'MEMBER [= VALUE]'"),
+        };
+        // The value after the last one overflows, only an error if something takes it.
+        let Some(value) = value else {
+            bail!("the {} '{}' overflows", kind.member(), member);
+        };
+
+        self.define_member(name, member, value, orig_idx)?;
+        if let Some(layout) = &mut self.layout {
+            layout.next = next;
+        }
+        Ok(true)
+    }
+
+    /// Defines the constant `NAME.MEMBER` of the layout `name`.
+    fn define_member(
+        &mut self,
+        name: &str,
+        member: &str,
+        value: u32,
+        orig_idx: usize,
+    ) -> Result<()> {
+        let kind = self.layout.as_ref().map_or(Kind::Struct, |l| l.kind);
+        if member.is_empty() || member.contains('.') {
+            bail!("invalid {} name '{}'", kind.member(), member);
+        }
+
+        let qualified = format!("{}.{}", name, member);
+        if let Some(old) = self.members.get(&qualified) {
+            let line = old.line.unwrap_or_default();
+            bail!("'{}' is already defined at line {}", qualified, line + 1);
+        }
+        self.members.insert(
+            qualified,
+            Constant {
+                value: OperandValue::Unsigned(value),
+                line: Some(orig_idx),
+                reassignable: false,
+            },
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use std::fmt::Display;
+
+use anyhow::{Result, bail};
+
+use crate::{instructions::parse_reg_s, operand::OperandValue, pass1::Pass1};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    If,
+    While,
+    For,
+}
+
+impl Kind {
+    fn end(self) -> &'static str {
+        match self {
+            Kind::If => ".endif",
+            Kind::While => ".endwhile",
+            Kind::For => ".endfor",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::If => write!(f, ".if"),
+            Kind::While => write!(f, ".while"),
+            Kind::For => write!(f, ".for"),
+        }
+    }
+}
+
+/// An open `.if`, `.while` or `.for` block.
+pub struct Block<'a> {
+    pub kind: Kind,
+    /// Index of the line opening the block.
+    pub line: usize,
+    /// The open `.if` blocks of conditional assembly when the block started.
+    pub conditionals: usize,
+    /// Numbers the generated labels of the block.
+    id: usize,
+    /// The address a loop jumps back to.
+    head: usize,
+    /// The branch skipping to the next branch of an `.if` when its test fails.
+    next_branch: Option<usize>,
+    /// The jumps to the end of the block.
+    exits: Vec<usize>,
+    /// The jumps of `.continue` to the increment of a `.for`.
+    continues: Vec<usize>,
+    in_else: bool,
+    /// The counter of a `.for`.
+    counter: Option<OperandValue<'a>>,
+}
+
+/// A condition, the branch taken when it holds and the inverse condition.
+const BRANCHES: [(&str, &str, &str); 6] = [
+    ("eq", "beq", "ne"),
+    ("ne", "bne", "eq"),
+    ("lt", "blt", "ge"),
+    ("ge", "bge", "lt"),
+    ("gt", "bgt", "le"),
+    ("le", "ble", "gt"),
+];
+
+fn branch(cond: &str) -> Option<&'static str> {
+    BRANCHES
+        .iter()
+        .find(|&&(c, ..)| c == cond)
+        .map(|&(_, branch, _)| branch)
+}
+
+pub fn inverse(cond: &str) -> Option<&'static str> {
+    BRANCHES
+        .iter()
+        .find(|&&(c, ..)| c == cond)
+        .map(|&(.., inverse)| inverse)
+}
+
+/// Whether `operands` are a test like `r1 lt r2`, comparing with a condition.
+pub fn is_test(operands: &[&str]) -> bool {
+    matches!(operands, [_, cond, _] if branch(cond).is_some())
+}
+
+impl<'a> Pass1<'a> {
+    /// Lowers the directives of structured control flow to branches, returns whether
+    /// `tokens` is one of them.
+    ///
+    /// `.if A COND B` skips to its `.elif`, `.else` or `.endif` with the inverse branch,
+    /// `.while A COND B` tests before each iteration, and `.for REG = A to B` counts `REG`
+    /// from `A` up to `B` included. `.break` and `.continue` leave or restart the innermost
+    /// loop, if their test like `.break r1 eq 0` or condition like `.break.eq` holds.
+    ///
+    /// Targets are patched once known, and each one gets a label like `main.endwhile1` for
+    /// listings.
+    pub(super) fn structured(
+        &mut self,
+        tokens: &[&'a str],
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<bool> {
+        // `.break` and `.continue` may be predicated like `.break.eq`.
+        let (directive, cond) = match tokens[0].rsplit_once('.') {
+            Some((directive @ (".break" | ".continue"), cond)) => (directive, Some(cond)),
+            _ => (tokens[0], None),
+        };
+        let operands = &tokens[1..];
+        let no_operands = || match operands {
+            [] => Ok(()),
+            _ => bail!("unexpected operands"),
+        };
+
+        match directive {
+            ".if" => {
+                let mut block = self.open(Kind::If, orig_idx);
+                block.next_branch = Some(self.test(operands, true, None, orig_idx, raw_line)?);
+                self.blocks.push(block);
+            }
+            ".elif" | ".else" => {
+                if tokens[0] == ".else" {
+                    no_operands()?;
+                }
+                let (id, next_branch) = match self.blocks.last() {
+                    Some(b) if b.kind == Kind::If && b.in_else => {
+                        bail!(
+                            "{} after the .else of the .if at line {}",
+                            tokens[0],
+                            b.line + 1
+                        )
+                    }
+                    Some(b) if b.kind == Kind::If => (b.id, b.next_branch),
+                    _ => bail!("no matching .if"),
+                };
+
+                let exit = match self.reachable() {
+                    true => Some(self.jump("jmp", None, Vec::new(), None, orig_idx, raw_line)?),
+                    false => None,
+                };
+                if let Some(next_branch) = next_branch {
+                    self.patch(next_branch, &tokens[0][1..], id);
+                }
+                let next_branch = match tokens[0] {
+                    ".elif" => Some(self.test(operands, true, None, orig_idx, raw_line)?),
+                    _ => None,
+                };
+
+                let Some(block) = self.blocks.last_mut() else {
+                    unreachable!()
+                };
+                block.exits.extend(exit);
+                block.next_branch = next_branch;
+                block.in_else = tokens[0] == ".else";
+            }
+            ".endif" => {
+                no_operands()?;
+                let block = self.close(Kind::If)?;
+                if let Some(next_branch) = block.next_branch {
+                    self.patch(next_branch, "endif", block.id);
+                }
+                self.exit(&block, "endif");
+            }
+            ".while" => {
+                let mut block = self.open(Kind::While, orig_idx);
+                self.label("while", block.id);
+                if !operands.is_empty() {
+                    let exit = self.test(operands, true, None, orig_idx, raw_line)?;
+                    block.exits.push(exit);
+                }
+                self.blocks.push(block);
+            }
+            ".endwhile" => {
+                no_operands()?;
+                let block = self.close(Kind::While)?;
+                self.jump(
+                    "jmp",
+                    None,
+                    Vec::new(),
+                    Some(block.head),
+                    orig_idx,
+                    raw_line,
+                )?;
+                self.exit(&block, "endwhile");
+            }
+            ".for" => {
+                let &[counter, "=", from, "to", to] = operands else {
+                    bail!("expected '.for REG = FROM to TO'");
+                };
+                let counter = self.substitute(counter);
+                let from = self.substitute(from);
+                let init = if parse_reg_s(&from).is_ok() {
+                    "mv"
+                } else {
+                    "li"
+                };
+                self.push(init, None, vec![counter, from], orig_idx, raw_line)?;
+
+                let mut block = self.open(Kind::For, orig_idx);
+                self.label("for", block.id);
+                let to = self.substitute(to);
+                let exit = self.jump("bgt", None, vec![counter, to], None, orig_idx, raw_line)?;
+                block.exits.push(exit);
+                block.counter = Some(counter);
+                self.blocks.push(block);
+            }
+            ".endfor" => {
+                no_operands()?;
+                let block = self.close(Kind::For)?;
+                for &jump in &block.continues {
+                    self.patch(jump, "next", block.id);
+                }
+                if block.continues.is_empty() {
+                    self.label("next", block.id);
+                }
+                let counter = block.counter.into_iter().collect();
+                self.push("inc", None, counter, orig_idx, raw_line)?;
+                self.jump(
+                    "jmp",
+                    None,
+                    Vec::new(),
+                    Some(block.head),
+                    orig_idx,
+                    raw_line,
+                )?;
+                self.exit(&block, "endfor");
+            }
+            ".break" | ".continue" => {
+                let Some(idx) = self.blocks.iter().rposition(|b| b.kind != Kind::If) else {
+                    bail!("not inside a .while or .for");
+                };
+                let target = match (directive, self.blocks[idx].kind) {
+                    (".continue", Kind::While) => Some(self.blocks[idx].head),
+                    _ => None,
+                };
+
+                let jump = match (operands, cond) {
+                    ([], _) => self.jump("jmp", cond, Vec::new(), target, orig_idx, raw_line)?,
+                    (_, None) => self.test(operands, false, target, orig_idx, raw_line)?,
+                    _ => bail!("expected either a condition or a test"),
+                };
+                match (directive, target) {
+                    (".break", _) => self.blocks[idx].exits.push(jump),
+                    (_, None) => self.blocks[idx].continues.push(jump),
+                    _ => {}
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn open(&mut self, kind: Kind, orig_idx: usize) -> Block<'a> {
+        self.block_count += 1;
+        Block {
+            kind,
+            line: orig_idx,
+            conditionals: self.conditionals.len(),
+            id: self.block_count,
+            head: self.processed.len(),
+            next_branch: None,
+            exits: Vec::new(),
+            continues: Vec::new(),
+            in_else: false,
+            counter: None,
+        }
+    }
+
+    /// Pops the innermost block, which must be a `kind` block with nothing left open in it.
+    fn close(&mut self, kind: Kind) -> Result<Block<'a>> {
+        let Some(block) = self.blocks.last() else {
+            bail!("no matching {}", kind);
+        };
+        if block.kind != kind {
+            bail!(
+                "expected {} for the {} at line {}",
+                block.kind.end(),
+                block.kind,
+                block.line + 1
+            );
+        }
+        if let Some(c) = self.conditionals.get(block.conditionals) {
+            bail!("unterminated .if at line {}", c.line + 1);
+        }
+        if let Some((repeat, _)) = self
+            .repeats
+            .last()
+            .filter(|(r, _)| r.blocks == self.blocks.len())
+        {
+            bail!("unterminated .rept at line {}", repeat.line + 1);
+        }
+
+        Ok(self.blocks.pop().unwrap())
+    }
+
+    /// Branches to `target` if the test `A COND B` holds, or fails if `inverted`, returns
+    /// the address of the branch.
+    fn test(
+        &mut self,
+        operands: &[&'a str],
+        inverted: bool,
+        target: Option<usize>,
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<usize> {
+        let &[a, cond, b] = operands else {
+            bail!("expected a test like 'r1 lt r2'");
+        };
+        let name = match inverted {
+            true => inverse(cond).and_then(branch),
+            false => branch(cond),
+        };
+        let Some(name) = name else {
+            bail!("unknown condition '{}'", cond);
+        };
+
+        let ops = vec![self.substitute(a), self.substitute(b)];
+        self.jump(name, None, ops, target, orig_idx, raw_line)
+    }
+
+    /// Whether the next address can be reached, a branch ending with a jump away needs no
+    /// jump over the other branches.
+    fn reachable(&self) -> bool {
+        let next = self.processed.len();
+        !matches!(self.processed.last(), Some(("jmp" | "ret", None, _)))
+            || self.labels.contains_right(&next)
+            || self.local_labels.values().any(|&addr| addr == next)
+            || self.anonymous_labels.iter().any(|&(_, addr)| addr == next)
+            || self.generated_labels.contains_key(&next)
+    }
+
+    /// Appends a jump to `target`, or to a target patched later if `None`, returns its address.
+    fn jump(
+        &mut self,
+        name: &'a str,
+        cond: Option<&'a str>,
+        mut ops: Vec<OperandValue<'a>>,
+        target: Option<usize>,
+        orig_idx: usize,
+        raw_line: &'a str,
+    ) -> Result<usize> {
+        ops.push(OperandValue::Unsigned(target.unwrap_or(0) as u32));
+        self.push(name, cond, ops, orig_idx, raw_line)?;
+        Ok(self.processed.len() - 1)
+    }
+
+    /// Points the jump at `jump` to the next address, labeled `name`.
+    pub(super) fn patch(&mut self, jump: usize, name: &str, id: usize) {
+        let target = self.processed.len();
+        if let Some(op) = self.processed[jump].2.last_mut() {
+            *op = OperandValue::Unsigned(target as u32);
+        }
+        self.label(name, id);
+    }
+
+    fn exit(&mut self, block: &Block, name: &str) {
+        for &jump in &block.exits {
+            self.patch(jump, name, block.id);
+        }
+        self.label(name, block.id);
+    }
+
+    /// Names the next address for listings, unless a generated label names it already.
+    fn label(&mut self, name: &str, id: usize) {
+        let label = format!("{}.{}{}", self.scope.unwrap_or_default(), name, id);
+        self.generated_labels
+            .entry(self.processed.len())
+            .or_insert(label);
+    }
+}
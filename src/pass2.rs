@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::{Result, anyhow};
 use bimap::BiHashMap;
 
@@ -13,19 +15,28 @@ use crate::{
 /// 3. Encode assembly instructions into machine code.
 pub struct Pass2<'a> {
     labels: BiHashMap<&'a str, usize>,
+    /// The labels of structured control flow, shown where no label is defined.
+    generated_labels: HashMap<usize, String>,
     addr_to_original: Vec<(usize, &'a str)>,
+    pub referenced_labels: HashSet<&'a str>,
 }
 
 impl<'a> Pass2<'a> {
-    pub fn new(labels: BiHashMap<&'a str, usize>, addr_to_original: Vec<(usize, &'a str)>) -> Self {
+    pub fn new(
+        labels: BiHashMap<&'a str, usize>,
+        generated_labels: HashMap<usize, String>,
+        addr_to_original: Vec<(usize, &'a str)>,
+    ) -> Self {
         Pass2 {
             labels,
+            generated_labels,
             addr_to_original,
+            referenced_labels: HashSet::new(),
         }
     }
 
     pub fn run(
-        &self,
+        &mut self,
         processed_lines: Vec<(&'a str, Option<&'a str>, Vec<OperandValue<'a>>)>,
     ) -> Result<(Vec<u32>, Vec<String>)> {
         let mut codes = Vec::new();
@@ -49,7 +60,12 @@ impl<'a> Pass2<'a> {
                 display += "\t";
             }
 
-            if let Some(label_name) = self.labels.get_by_right(&codes.len()) {
+            let label_name = self
+                .labels
+                .get_by_right(&codes.len())
+                .copied()
+                .or_else(|| self.generated_labels.get(&codes.len()).map(String::as_str));
+            if let Some(label_name) = label_name {
                 display = format!("{display}\t<label: {label_name}>");
             } else {
                 display += "\t";
@@ -63,7 +79,7 @@ impl<'a> Pass2<'a> {
     }
 
     fn line_handler(
-        &self,
+        &mut self,
         line: (&'a str, Option<&'a str>, Vec<OperandValue<'a>>),
     ) -> Result<(u32, String)> {
         let (name, cond, operands) = line;
@@ -74,6 +90,7 @@ impl<'a> Pass2<'a> {
                 if let Some(s) = e.as_str()
                     && let Some(&label_addr) = self.labels.get_by_left(s)
                 {
+                    self.referenced_labels.insert(s);
                     OperandValue::Unsigned(label_addr.try_into().unwrap()) // WARN: unsafe
                 } else {
                     e
@@ -53,7 +53,7 @@ impl PseudoInstruction {
             match &self.operand_types[i] {
                 OperandType::RegD => parse_reg_d(operand)?,
                 OperandType::RegS => parse_reg_s(operand)?,
-                OperandType::Imm(_) => unimplemented!(),
+                OperandType::Imm(_) | OperandType::Color => unimplemented!(),
             };
         }
 
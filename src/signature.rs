@@ -0,0 +1,124 @@
+use std::fmt::Display;
+
+use anyhow::{Result, bail};
+
+use crate::{instructions::parse_reg_s, operand::OperandValue};
+
+/// The calling convention of a routine, declared with a `.func` line.
+///
+/// ```asm
+/// .func fib in a0 out rt clobbers r10,r11 saves s0,s1
+/// ```
+///
+/// Registers not listed as outputs or clobbered must be preserved, except `tmp`,
+/// which macro-instructions overwrite anyway. The saved registers are pushed on entry and
+/// popped before each `ret` up to the `.endfunc`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// Index of the `.func` line.
+    pub line: usize,
+    pub ins: Vec<u32>,
+    pub outs: Vec<u32>,
+    pub clobbers: Vec<u32>,
+    pub saves: Vec<u32>,
+    /// The lists as written in the source.
+    text: String,
+}
+
+impl Signature {
+    /// Parses the tokens after `.func NAME`, resolving register names with `resolve`.
+    pub fn parse<'a>(
+        line: usize,
+        tokens: &[&'a str],
+        resolve: impl Fn(&'a str) -> &'a str,
+    ) -> Result<Self> {
+        let mut signature = Signature {
+            line,
+            ins: Vec::new(),
+            outs: Vec::new(),
+            clobbers: Vec::new(),
+            saves: Vec::new(),
+            text: String::new(),
+        };
+
+        let mut parts = Vec::new();
+        let mut current: Option<(&str, Vec<&str>)> = None;
+
+        for &token in tokens {
+            match token {
+                "in" | "out" | "clobbers" | "saves" => {
+                    parts.extend(current.take());
+                    current = Some((token, Vec::new()));
+                }
+                _ => match &mut current {
+                    Some((_, names)) => {
+                        names.extend(token.split(',').filter(|name| !name.is_empty()))
+                    }
+                    None => bail!(
+                        "Expected 'in', 'out', 'clobbers' or 'saves', found '{}'",
+                        token
+                    ),
+                },
+            }
+        }
+        parts.extend(current);
+
+        let mut text = Vec::new();
+        for (keyword, names) in parts {
+            let regs = names
+                .iter()
+                .map(|&name| parse_reg_s(&OperandValue::from(resolve(name))))
+                .collect::<Result<Vec<_>>>()?;
+
+            match keyword {
+                "in" => signature.ins.extend(regs),
+                "out" => signature.outs.extend(regs),
+                "saves" => signature.saves.extend(regs),
+                _ => signature.clobbers.extend(regs),
+            }
+            text.push(format!("{} {}", keyword, names.join(",")));
+        }
+
+        signature.text = text.join(" ");
+        Ok(signature)
+    }
+
+    /// Whether the routine may change `reg` without violating the signature.
+    pub fn may_write(&self, reg: u32) -> bool {
+        reg == 31 || self.outs.contains(&reg) || self.clobbers.contains(&reg)
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn parse() {
+        let f = |s: &str| {
+            let tokens = s.split_whitespace().collect::<Vec<_>>();
+            let resolve = |name| if name == "rt" { "r13" } else { name };
+            match Signature::parse(0, &tokens, resolve) {
+                Ok(sig) => format!(
+                    "{:?} {:?} {:?} {:?} '{}'",
+                    sig.ins, sig.outs, sig.clobbers, sig.saves, sig
+                ),
+                Err(e) => format!("Error: {e}"),
+            }
+        };
+
+        assert_snapshot!(f(""), @"[] [] [] [] ''");
+        assert_snapshot!(f("in r1,r2 out rt clobbers r10, r11"), @"[1, 2] [13] [10, 11] [] 'in r1,r2 out rt clobbers r10,r11'");
+        assert_snapshot!(f("out r1 in r2 in r3"), @"[2, 3] [1] [] [] 'out r1 in r2 in r3'");
+        assert_snapshot!(f("in r1 saves r4,r5"), @"[1] [] [] [4, 5] 'in r1 saves r4,r5'");
+        assert_snapshot!(f("r1"), @"Error: Expected 'in', 'out', 'clobbers' or 'saves', found 'r1'");
+        assert_snapshot!(f("in x"), @"Error: Invalid register: x");
+    }
+}
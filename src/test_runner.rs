@@ -0,0 +1,452 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    assembler::Program,
+    emulator::{Effect, Emulator, EmulatorSettings, MachineProfile},
+    instructions::{parse_imm, parse_reg_s},
+    utils::fmt_hex,
+};
+
+const DEFAULT_MAX_CYCLES: u64 = 100_000;
+
+/// A test block, written in comments starting with `#>` or `;>`.
+///
+/// ```asm
+/// #> test fib_10
+/// #> set a0 10
+/// #> call fib
+/// #> expect rt 55
+/// #> expect stack 0
+/// ```
+///
+/// Setup directives: `set REG VALUE`, `mem ADDR VALUE`, `input VALUE...`,
+/// `keys VALUE...` and `max-cycles N`. Exactly one `call LABEL` is required.
+///
+/// Assertions: `expect REG VALUE`, `expect mem ADDR VALUE`, `expect stack DEPTH`,
+/// `expect pixel X Y COLOR`, `expect pixels COUNT`, `expect seg VALUE` and
+/// `expect output VALUE...`.
+///
+/// Constants defined before the first label can be used in place of registers and values.
+struct TestCase<'a> {
+    name: &'a str,
+    line: usize,
+    directives: Vec<(usize, Vec<&'a str>)>,
+}
+
+/// The outcome of a single test case.
+struct Outcome {
+    cycles: u64,
+    failures: Vec<String>,
+}
+
+/// Display events observed during a test.
+#[derive(Default)]
+struct DisplayState {
+    pixels: HashMap<(u32, u32), u32>,
+    pixel_count: u32,
+    segment: Option<u32>,
+}
+
+pub struct TestRunner<'a> {
+    program: &'a Program,
+    cases: Vec<TestCase<'a>>,
+    max_cycles: u64,
+    machine: MachineProfile,
+}
+
+impl<'a> TestRunner<'a> {
+    pub fn new(program: &'a Program, source_lines: &'a [String]) -> Result<Self> {
+        let mut cases: Vec<TestCase> = Vec::new();
+
+        for (idx, line) in source_lines.iter().enumerate() {
+            let line = line.trim();
+            let Some(directive) = line.strip_prefix("#>").or_else(|| line.strip_prefix(";>"))
+            else {
+                continue;
+            };
+
+            let tokens = directive.split_whitespace().collect::<Vec<_>>();
+            match tokens.as_slice() {
+                [] => continue,
+                ["test", name] => cases.push(TestCase {
+                    name,
+                    line: idx,
+                    directives: Vec::new(),
+                }),
+                ["test", ..] => bail!("Malformed test at line {}: '{}'", idx + 1, line),
+                _ => match cases.last_mut() {
+                    Some(case) => case.directives.push((idx, tokens)),
+                    None => bail!(
+                        "Test directive outside of a test at line {}: '{}'",
+                        idx + 1,
+                        line
+                    ),
+                },
+            }
+        }
+
+        Ok(TestRunner {
+            program,
+            cases,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            machine: MachineProfile::default(),
+        })
+    }
+
+    pub fn with_max_cycles(mut self, max_cycles: u64) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    pub fn with_machine(mut self, machine: MachineProfile) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cases.is_empty()
+    }
+
+    /// Runs all tests and writes a report to `out`, returns `(passed, failed)`.
+    pub fn run(&self, out: &mut impl Write) -> Result<(usize, usize)> {
+        let (mut passed, mut failed) = (0, 0);
+
+        for case in &self.cases {
+            let outcome = self.run_case(case);
+
+            if outcome.failures.is_empty() {
+                passed += 1;
+                writeln!(out, "test {} ... ok ({} cycles)", case.name, outcome.cycles)?;
+            } else {
+                failed += 1;
+                writeln!(
+                    out,
+                    "test {} ... FAILED (line {})",
+                    case.name,
+                    case.line + 1
+                )?;
+                for failure in &outcome.failures {
+                    writeln!(out, "    {}", failure)?;
+                }
+            }
+        }
+
+        Ok((passed, failed))
+    }
+
+    fn run_case(&self, case: &TestCase) -> Outcome {
+        let mut outcome = Outcome {
+            cycles: 0,
+            failures: Vec::new(),
+        };
+
+        let mut settings = EmulatorSettings {
+            inputs: Vec::new(),
+            keys: Vec::new(),
+            seed: 1,
+            machine: self.machine.clone(),
+        };
+        let mut max_cycles = self.max_cycles;
+        let mut target = None;
+        let mut setup = Vec::new();
+        let mut expects = Vec::new();
+
+        for (idx, tokens) in &case.directives {
+            let result = match tokens.as_slice() {
+                ["input", values @ ..] => self.values(values).map(|v| settings.inputs = v),
+                ["keys", values @ ..] => self.values(values).map(|v| settings.keys = v),
+                ["max-cycles", n] => n
+                    .parse()
+                    .map(|n| max_cycles = n)
+                    .map_err(|_| anyhow!("Invalid cycle limit: {}", n)),
+                ["call", label] => match self.program.labels.get_by_left(*label) {
+                    Some(&addr) => {
+                        target = Some(addr);
+                        Ok(())
+                    }
+                    None => Err(anyhow!("Unknown label: '{}'", label)),
+                },
+                ["set" | "mem", ..] => {
+                    setup.push((*idx, tokens.as_slice()));
+                    Ok(())
+                }
+                ["expect", ..] => {
+                    expects.push((*idx, tokens.as_slice()));
+                    Ok(())
+                }
+                _ => Err(anyhow!("Unknown test directive")),
+            };
+
+            if let Err(e) = result {
+                outcome
+                    .failures
+                    .push(format!("line {}: {} ({})", idx + 1, tokens.join(" "), e));
+            }
+        }
+
+        let Some(target) = target else {
+            outcome.failures.push("missing 'call LABEL'".to_string());
+            return outcome;
+        };
+        if !outcome.failures.is_empty() {
+            return outcome;
+        }
+
+        let mut emu = Emulator::new(settings, &self.program.codes);
+
+        for (idx, tokens) in setup {
+            if let Err(e) = self.setup(&mut emu, tokens) {
+                outcome
+                    .failures
+                    .push(format!("line {}: {} ({})", idx + 1, tokens.join(" "), e));
+            }
+        }
+
+        let mut display = DisplayState::default();
+        emu.call(target);
+
+        while emu.pc() != self.program.codes.len() {
+            if let Some(halt) = emu.halted() {
+                outcome.failures.push(format!(
+                    "halted ({}) at {}",
+                    halt,
+                    self.program.location(emu.pc())
+                ));
+                break;
+            }
+
+            if emu.cycle() >= max_cycles {
+                outcome.failures.push(format!(
+                    "did not return within {} cycles, stopped at {}",
+                    max_cycles,
+                    self.program.location(emu.pc())
+                ));
+                break;
+            }
+
+            let pc = emu.pc();
+            match emu.step() {
+                Ok(step) => {
+                    for effect in step.effects {
+                        match effect {
+                            Effect::Pixel { x, y, color } => {
+                                display.pixels.insert((x, y), color);
+                                display.pixel_count += 1;
+                            }
+                            Effect::Segment(value) => display.segment = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+                Err(e) => {
+                    outcome
+                        .failures
+                        .push(format!("{} ({})", self.program.location(pc), e));
+                    break;
+                }
+            }
+        }
+
+        outcome.cycles = emu.cycle();
+
+        for (idx, tokens) in expects {
+            match self.check(&emu, &display, tokens) {
+                Ok(None) => {}
+                Ok(Some(failure)) => {
+                    outcome
+                        .failures
+                        .push(format!("line {}: {}", idx + 1, failure))
+                }
+                Err(e) => {
+                    outcome
+                        .failures
+                        .push(format!("line {}: {} ({})", idx + 1, tokens.join(" "), e))
+                }
+            }
+        }
+
+        outcome
+    }
+
+    fn setup(&self, emu: &mut Emulator, tokens: &[&str]) -> Result<()> {
+        match tokens {
+            ["set", reg, value] => emu.set_reg(self.reg(reg)?, self.value(value)?),
+            ["mem", addr, value] => emu.set_mem(
+                self.machine.map_addr(self.value(addr)?)?,
+                self.value(value)?,
+            ),
+            _ => bail!("Malformed setup"),
+        }
+
+        Ok(())
+    }
+
+    /// Returns a description of the failure, if any.
+    fn check(
+        &self,
+        emu: &Emulator,
+        display: &DisplayState,
+        tokens: &[&str],
+    ) -> Result<Option<String>> {
+        let (what, expected, actual) = match tokens {
+            ["expect", "mem", addr, value] => {
+                let addr = self.value(addr)?;
+                (
+                    format!("[{}]", fmt_hex(addr)),
+                    self.value(value)?,
+                    emu.mem(addr),
+                )
+            }
+            ["expect", "stack", depth] => (
+                "stack depth".to_string(),
+                self.value(depth)?,
+                emu.stack_depth() as u32,
+            ),
+            ["expect", "pixel", x, y, color] => {
+                let (x, y) = (self.value(x)?, self.value(y)?);
+                let Some(&actual) = display.pixels.get(&(x, y)) else {
+                    return Ok(Some(format!("pixel ({}, {}) was never drawn", x, y)));
+                };
+                (format!("pixel ({}, {})", x, y), self.value(color)?, actual)
+            }
+            ["expect", "pixels", count] => (
+                "pixel count".to_string(),
+                self.value(count)?,
+                display.pixel_count,
+            ),
+            ["expect", "seg", value] => {
+                let Some(actual) = display.segment else {
+                    return Ok(Some("segment display was never set".to_string()));
+                };
+                ("segment display".to_string(), self.value(value)?, actual)
+            }
+            ["expect", "output", values @ ..] => {
+                let expected = self.values(values)?;
+                if expected == emu.outputs {
+                    return Ok(None);
+                }
+                return Ok(Some(format!(
+                    "expected output {:?}, got {:?}",
+                    expected, emu.outputs
+                )));
+            }
+            ["expect", reg, value] => {
+                (reg.to_string(), self.value(value)?, emu.reg(self.reg(reg)?))
+            }
+            _ => bail!("Malformed assertion"),
+        };
+
+        if expected == actual {
+            Ok(None)
+        } else {
+            Ok(Some(format!(
+                "expected {} = {}, got {}",
+                what,
+                fmt_hex(expected),
+                fmt_hex(actual)
+            )))
+        }
+    }
+
+    fn reg(&self, name: &str) -> Result<u32> {
+        parse_reg_s(&self.program.resolve_global(name).into())
+    }
+
+    fn value(&self, value: &str) -> Result<u32> {
+        parse_imm(&self.program.resolve_global(value).into())
+    }
+
+    fn values(&self, values: &[&str]) -> Result<Vec<u32>> {
+        values.iter().map(|v| self.value(v)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit::*;
+
+    #[test]
+    fn test_runner() {
+        let src = "
+            const a0 r1
+            const rt r2
+            const COLOR 0xFF0000
+
+            #> test double
+            #> set a0 21
+            #> call double
+            #> expect rt 42
+            #> expect stack 0
+
+            #> test draw
+            #> call draw
+            #> expect pixel 1 2 COLOR
+            #> expect pixels 1
+            #> expect seg 7
+            #> expect mem 3 9
+            #> expect output 5
+
+            ;> test failing
+            ;> set a0 1
+            ;> call double
+            ;> expect rt 3
+            ;> expect pixel 0 0 0
+            ;> expect r9
+
+            #> test forever
+            #> max-cycles 10
+            #> call forever
+
+            #> test halt
+            #> call halt
+
+            double:
+                add rt a0 a0
+                ret
+
+            draw:
+                col COLOR
+                li r3 1
+                li r4 2
+                spx r3 r4
+                segi 7
+                li r5 9
+                sw zero r5 3
+                li io 5
+                ret
+
+            forever:
+                const rt r9
+                inc r1
+                jmp forever
+
+            halt:
+                jmp halt
+        "
+        .lines()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+        let program = assemble(&src.join("\n"));
+
+        let runner = TestRunner::new(&program, &src).unwrap();
+        let mut out = Vec::new();
+        let result = runner.run(&mut out).unwrap();
+
+        assert_snapshot!(format!("{:?}\n{}", result, String::from_utf8(out).unwrap()), @r"
+        (2, 3)
+        test double ... ok (2 cycles)
+        test draw ... ok (9 cycles)
+        test failing ... FAILED (line 20)
+            line 23: expected rt = 3, got 2
+            line 24: pixel (0, 0) was never drawn
+            line 25: expect r9 (Malformed assertion)
+        test forever ... FAILED (line 27)
+            did not return within 10 cycles, stopped at line 51: 'inc r1'
+        test halt ... FAILED (line 31)
+            halted (jump to itself) at line 55: 'jmp halt'
+        ");
+    }
+}
@@ -1,6 +1,14 @@
 pub use insta::assert_snapshot;
 
-use crate::{instructions::*, macro_instructions::*, operand::OperandValue, utils::fmt_line};
+use crate::{
+    analysis::Warning,
+    assembler::{Assembler, AssemblerSettings, Program},
+    emulator::{Emulator, EmulatorSettings, MachineProfile},
+    instructions::*,
+    macro_instructions::*,
+    operand::OperandValue,
+    utils::{align_tabbed_lines, fmt_line},
+};
 
 pub fn instr(cmd: &str) -> impl Fn(&str, &[&str]) -> String {
     let instr = INSTRUCTIONS.get(cmd).unwrap();
@@ -49,3 +57,84 @@ fn fmt_bits(n: u32) -> String {
         .collect::<Vec<_>>()
         .join(" ")
 }
+
+pub fn assemble(src: &str) -> Program {
+    let source_lines = src.lines().map(|s| s.to_string()).collect();
+    let settings = AssemblerSettings {
+        disable_macro: false,
+        defines: Vec::new(),
+    };
+    Assembler::new(settings, source_lines).assemble().unwrap()
+}
+
+/// Formats the warnings ordered by line, as [`crate::analysis::analyze`] does.
+pub fn fmt_warnings(warnings: &[Warning]) -> String {
+    let mut warnings = warnings.to_vec();
+    warnings.sort_by_key(|w| w.line);
+
+    warnings
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn emulator(program: &Program) -> Emulator<'_> {
+    emulator_with(program, MachineProfile::default())
+}
+
+pub fn emulator_with(program: &Program, machine: MachineProfile) -> Emulator<'_> {
+    let settings = EmulatorSettings {
+        inputs: Vec::new(),
+        keys: Vec::new(),
+        seed: 1,
+        machine,
+    };
+    Emulator::new(settings, &program.codes)
+}
+
+/// Runs until the program halts, an error occurs or `max_cycles` is reached.
+pub fn run(program: &Program, max_cycles: u64) -> (Emulator<'_>, String) {
+    run_with(program, MachineProfile::default(), max_cycles)
+}
+
+pub fn run_with(
+    program: &Program,
+    machine: MachineProfile,
+    max_cycles: u64,
+) -> (Emulator<'_>, String) {
+    let mut emu = emulator_with(program, machine);
+    while emu.cycle() < max_cycles {
+        if let Some(halt) = emu.halted() {
+            return (emu, format!("Halted: {halt}"));
+        }
+        if let Err(e) = emu.step() {
+            return (emu, format!("Error: {e}"));
+        }
+    }
+    (emu, "Ok".to_string())
+}
+
+/// Runs `cycles` words, formatting each as `+/- <asm> <effects>`.
+pub fn steps(program: &Program, cycles: u64) -> String {
+    let mut emu = emulator(program);
+    let lines = (0..cycles)
+        .map(|_| {
+            let step = emu.step().unwrap();
+            let effects = step
+                .effects
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!(
+                "{} {}\t{}",
+                if step.executed { '+' } else { '-' },
+                disassemble(step.code).unwrap(),
+                effects
+            )
+        })
+        .collect::<Vec<_>>();
+
+    align_tabbed_lines(&lines).collect::<Vec<_>>().join("\n")
+}
@@ -1,27 +1,111 @@
-use std::process::Command;
+use std::process::{Command, Output};
 
-use insta::{assert_snapshot, glob, with_settings};
+use insta::{Settings, assert_snapshot, glob};
 use insta_cmd::get_cargo_bin;
 
 #[test]
 fn examples() {
-    with_settings!({
-        prepend_module_to_snapshot => false,
-        omit_expression => true,
-    }, {
+    settings().bind(|| {
         glob!("../examples", "*.asm", |path| {
             let output = cli().arg(path).env("RUST_LOG", "debug").output().unwrap();
-            assert_snapshot!(format!(
-                "success: {}\nexit_code: {}\n----- stdout -----\n{}----- stderr -----\n{}",
-                output.status.success(),
-                output.status.code().unwrap(),
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            ))
+            assert_snapshot!(snapshot(output))
         })
     })
 }
 
+#[test]
+fn run_examples() {
+    settings().bind(|| {
+        glob!("../examples", "*.asm", |path| {
+            let output = cli()
+                .arg("run")
+                .arg(path)
+                .args(["--max-cycles", "200000"])
+                .env("RUST_BACKTRACE", "0")
+                .output()
+                .unwrap();
+            assert_snapshot!(snapshot(output))
+        })
+    })
+}
+
+#[test]
+fn asm_tests() {
+    settings().bind(|| {
+        let output = cli().arg("test").arg("examples").output().unwrap();
+        assert_snapshot!(snapshot(output))
+    })
+}
+
+#[test]
+fn stack_examples() {
+    settings().bind(|| {
+        glob!("../examples", "*.asm", |path| {
+            let output = cli().arg("stack").arg(path).output().unwrap();
+            assert_snapshot!(snapshot(output))
+        })
+    })
+}
+
+#[test]
+fn cfg_dot() {
+    settings().bind(|| {
+        let output = cli()
+            .arg("cfg")
+            .arg("examples/fib.asm")
+            .args(["--function", "fib"])
+            .output()
+            .unwrap();
+        assert_snapshot!(snapshot(output))
+    })
+}
+
+#[test]
+fn symbols() {
+    settings().bind(|| {
+        let output = cli()
+            .arg("examples/fib.asm")
+            .args(["--symbols", "<stdout>", "-o", "/dev/null"])
+            .output()
+            .unwrap();
+        assert_snapshot!(snapshot(output))
+    })
+}
+
+#[test]
+fn lint() {
+    settings().bind(|| {
+        let output = cli().arg("lint").arg("examples/fib.asm").output().unwrap();
+        assert_snapshot!(snapshot(output))
+    })
+}
+
+#[test]
+fn fmt_check() {
+    settings().bind(|| {
+        let output = cli().args(["fmt", "--check", "examples"]).output().unwrap();
+        assert_snapshot!(snapshot(output))
+    })
+}
+
 fn cli() -> Command {
     Command::new(get_cargo_bin(env!("CARGO_PKG_NAME")))
 }
+
+/// Snapshots are named after the test alone and hold only the output of the command.
+fn settings() -> Settings {
+    let mut settings = Settings::clone_current();
+    settings.set_prepend_module_to_snapshot(false);
+    settings.set_omit_expression(true);
+    settings
+}
+
+fn snapshot(output: Output) -> String {
+    format!(
+        "success: {}\nexit_code: {}\n----- stdout -----\n{}----- stderr -----\n{}",
+        output.status.success(),
+        output.status.code().unwrap(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}